@@ -37,4 +37,16 @@ pub enum SpellError {
     SetTriggerConfigForbidden,
     #[error("Trigger Config is not set. Use set_trigger_config to set it.")]
     NoTriggerConfig,
+    #[error("Only owner of the spell can set limits")]
+    SetLimitsForbidden,
+    #[error("Only the host can set KV quotas")]
+    SetQuotasForbidden,
+    #[error("KV quota exceeded: {0}")]
+    QuotaExceeded(String),
+    #[error("Only owner of the spell can set mailbox sender policy")]
+    SetMailboxPolicyForbidden,
+    #[error("Peer {0} can't push to the mailbox under the `{1}` sender policy")]
+    MailboxSenderForbidden(String, String),
+    #[error("Peer {0} exceeded the mailbox rate limit of {1} messages per {2} seconds")]
+    MailboxRateLimited(String, u32, u32),
 }
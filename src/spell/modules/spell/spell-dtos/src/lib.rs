@@ -15,5 +15,10 @@
  */
 
 pub mod error;
+pub mod limits;
+pub mod mailbox;
+pub mod quota;
+pub mod run;
+pub mod stats;
 pub mod trigger_config;
 pub mod value;
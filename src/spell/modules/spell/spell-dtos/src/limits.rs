@@ -0,0 +1,67 @@
+/*
+ * Copyright 2024 Fluence DAO
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::value::{format_error, SpellValueT};
+use marine_rs_sdk::marine;
+use serde::{Deserialize, Serialize};
+
+#[marine]
+#[derive(Default, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+/// How many of the latest rows the spell keeps in its logs, mailbox, particle errors and runs
+pub struct Limits {
+    pub max_logs: u32,
+    pub max_mailbox: u32,
+    /// errors are kept for this number of the latest failed particles
+    pub max_particles: u32,
+    pub max_runs: u32,
+    /// errors stored more than this number of seconds ago are deleted, 0 keeps them until evicted
+    pub max_error_age_sec: u32,
+}
+
+#[marine]
+#[derive(Default, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct LimitsValue {
+    pub limits: Limits,
+    pub success: bool,
+    pub error: String,
+}
+
+impl From<eyre::Result<Limits>> for LimitsValue {
+    fn from(result: eyre::Result<Limits>) -> Self {
+        match result {
+            Ok(limits) => LimitsValue {
+                limits,
+                success: true,
+                error: String::new(),
+            },
+            Err(e) => LimitsValue {
+                limits: <_>::default(),
+                success: false,
+                error: format_error(e),
+            },
+        }
+    }
+}
+
+impl SpellValueT for LimitsValue {
+    fn is_success(&self) -> bool {
+        self.success
+    }
+
+    fn take_error(self) -> String {
+        self.error
+    }
+}
@@ -0,0 +1,68 @@
+/*
+ * Copyright 2024 Fluence DAO
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::value::{format_error, SpellValueT};
+use marine_rs_sdk::marine;
+use serde::{Deserialize, Serialize};
+
+#[marine]
+#[derive(Default, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+/// Who can push messages to the mailbox and how often.
+/// The spell itself, its worker and its host can always push and aren't rate limited.
+pub struct MailboxPolicy {
+    /// `open` for any peer, `allowlist` for the peers from the `allowlist`
+    /// or `trusted` for the spell, its worker and its host only
+    pub mode: String,
+    pub allowlist: Vec<String>,
+    /// number of messages a peer can push in `rate_window_sec` seconds, 0 for no limit
+    pub rate_limit: u32,
+    pub rate_window_sec: u32,
+}
+
+#[marine]
+#[derive(Default, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct MailboxPolicyValue {
+    pub policy: MailboxPolicy,
+    pub success: bool,
+    pub error: String,
+}
+
+impl From<eyre::Result<MailboxPolicy>> for MailboxPolicyValue {
+    fn from(result: eyre::Result<MailboxPolicy>) -> Self {
+        match result {
+            Ok(policy) => MailboxPolicyValue {
+                policy,
+                success: true,
+                error: String::new(),
+            },
+            Err(e) => MailboxPolicyValue {
+                policy: <_>::default(),
+                success: false,
+                error: format_error(e),
+            },
+        }
+    }
+}
+
+impl SpellValueT for MailboxPolicyValue {
+    fn is_success(&self) -> bool {
+        self.success
+    }
+
+    fn take_error(self) -> String {
+        self.error
+    }
+}
@@ -0,0 +1,74 @@
+/*
+ * Copyright 2024 Fluence DAO
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::value::{format_error, SpellValueT};
+use marine_rs_sdk::marine;
+use serde::{Deserialize, Serialize};
+
+#[marine]
+#[derive(Default, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+/// How much data the spell can keep in its KV.
+/// Sizes are in bytes, keys and map fields are counted along with the values.
+pub struct Quotas {
+    /// number of distinct keys, including lists and maps
+    pub max_keys: u32,
+    /// size of a single written value
+    pub max_value_size: u32,
+    pub max_total_bytes: u64,
+}
+
+#[marine]
+#[derive(Default, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct QuotaBudget {
+    pub quotas: Quotas,
+    pub keys_left: u32,
+    pub bytes_left: u64,
+}
+
+#[marine]
+#[derive(Default, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct QuotaBudgetValue {
+    pub budget: QuotaBudget,
+    pub success: bool,
+    pub error: String,
+}
+
+impl From<eyre::Result<QuotaBudget>> for QuotaBudgetValue {
+    fn from(result: eyre::Result<QuotaBudget>) -> Self {
+        match result {
+            Ok(budget) => QuotaBudgetValue {
+                budget,
+                success: true,
+                error: String::new(),
+            },
+            Err(e) => QuotaBudgetValue {
+                budget: <_>::default(),
+                success: false,
+                error: format_error(e),
+            },
+        }
+    }
+}
+
+impl SpellValueT for QuotaBudgetValue {
+    fn is_success(&self) -> bool {
+        self.success
+    }
+
+    fn take_error(self) -> String {
+        self.error
+    }
+}
@@ -0,0 +1,121 @@
+/*
+ * Copyright 2024 Fluence DAO
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::value::{format_error, SpellValueT};
+use marine_rs_sdk::marine;
+use serde::{Deserialize, Serialize};
+
+#[marine]
+#[derive(Default, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+/// A run of the spell script recorded by `record_run`
+pub struct Run {
+    pub particle_id: String,
+    /// the counter from the `spell_<spell_id>_<counter>` particle id
+    pub counter: u64,
+    /// particle timestamp in milliseconds
+    pub particle_timestamp: u64,
+    /// unix time in seconds when the run was recorded
+    pub timestamp: u64,
+    /// true if there are errors stored for the particle of the run
+    pub has_errors: bool,
+}
+
+#[marine]
+#[derive(Default, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct RunHistory {
+    /// the latest runs, the latest first
+    pub runs: Vec<Run>,
+    pub success: bool,
+    pub error: String,
+}
+
+impl From<eyre::Result<Vec<Run>>> for RunHistory {
+    fn from(result: eyre::Result<Vec<Run>>) -> Self {
+        match result {
+            Ok(runs) => RunHistory {
+                runs,
+                success: true,
+                error: String::new(),
+            },
+            Err(e) => RunHistory {
+                runs: vec![],
+                success: false,
+                error: format_error(e),
+            },
+        }
+    }
+}
+
+impl SpellValueT for RunHistory {
+    fn is_success(&self) -> bool {
+        self.success
+    }
+
+    fn take_error(self) -> String {
+        self.error
+    }
+}
+
+#[marine]
+#[derive(Default, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+/// Only the latest `max_runs` runs are kept, so all the fields except `total_runs` describe them.
+pub struct RunStats {
+    /// number of runs recorded since the spell was installed
+    pub total_runs: u64,
+    pub stored_runs: u64,
+    /// number of stored runs that have errors stored for their particles
+    pub runs_with_errors: u64,
+    /// particle timestamp of the latest run in milliseconds, 0 if there were no runs
+    pub last_run_timestamp: u64,
+    /// average time between the particle timestamps of consecutive runs in milliseconds,
+    /// 0 if there are less than 2 runs
+    pub average_interval_ms: u64,
+}
+
+#[marine]
+#[derive(Default, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct RunStatsValue {
+    pub stats: RunStats,
+    pub success: bool,
+    pub error: String,
+}
+
+impl From<eyre::Result<RunStats>> for RunStatsValue {
+    fn from(result: eyre::Result<RunStats>) -> Self {
+        match result {
+            Ok(stats) => RunStatsValue {
+                stats,
+                success: true,
+                error: String::new(),
+            },
+            Err(e) => RunStatsValue {
+                stats: <_>::default(),
+                success: false,
+                error: format_error(e),
+            },
+        }
+    }
+}
+
+impl SpellValueT for RunStatsValue {
+    fn is_success(&self) -> bool {
+        self.success
+    }
+
+    fn take_error(self) -> String {
+        self.error
+    }
+}
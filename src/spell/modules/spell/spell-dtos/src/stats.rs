@@ -0,0 +1,85 @@
+/*
+ * Copyright 2024 Fluence DAO
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::value::{format_error, SpellValueT};
+use marine_rs_sdk::marine;
+use serde::{Deserialize, Serialize};
+
+#[marine]
+#[derive(Default, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct TableStats {
+    pub table: String,
+    pub rows: u64,
+    /// total size of the stored keys and values, numbers are counted by their decimal representation
+    pub bytes: u64,
+    /// value of the row counter kept in `config_table`, 0 for tables without a counter
+    pub counter: u64,
+    /// true if the counter doesn't match the actual number of rows
+    pub counter_drift: bool,
+}
+
+#[marine]
+#[derive(Default, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct KeySize {
+    pub key: String,
+    pub bytes: u64,
+}
+
+#[marine]
+#[derive(Default, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct StorageStats {
+    pub tables: Vec<TableStats>,
+    /// the largest KV keys with all their values, the largest first
+    pub largest_keys: Vec<KeySize>,
+    pub page_count: u64,
+    pub page_size: u64,
+    pub file_size: u64,
+}
+
+#[marine]
+#[derive(Default, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct StorageStatsValue {
+    pub stats: StorageStats,
+    pub success: bool,
+    pub error: String,
+}
+
+impl From<eyre::Result<StorageStats>> for StorageStatsValue {
+    fn from(result: eyre::Result<StorageStats>) -> Self {
+        match result {
+            Ok(stats) => StorageStatsValue {
+                stats,
+                success: true,
+                error: String::new(),
+            },
+            Err(e) => StorageStatsValue {
+                stats: <_>::default(),
+                success: false,
+                error: format_error(e),
+            },
+        }
+    }
+}
+
+impl SpellValueT for StorageStatsValue {
+    fn is_success(&self) -> bool {
+        self.success
+    }
+
+    fn take_error(self) -> String {
+        self.error
+    }
+}
@@ -162,6 +162,41 @@ impl SpellValueT for StringListValue {
     }
 }
 
+#[marine]
+#[derive(Debug, Deserialize)]
+pub struct U32ListValue {
+    pub value: Vec<u32>,
+    pub success: bool,
+    pub error: String,
+}
+
+impl From<eyre::Result<Vec<u32>>> for U32ListValue {
+    fn from(value: eyre::Result<Vec<u32>>) -> Self {
+        match value {
+            Ok(value) => U32ListValue {
+                value,
+                success: true,
+                error: String::new(),
+            },
+            Err(e) => U32ListValue {
+                value: vec![],
+                success: false,
+                error: format_error(e),
+            },
+        }
+    }
+}
+
+impl SpellValueT for U32ListValue {
+    fn is_success(&self) -> bool {
+        self.success
+    }
+
+    fn take_error(self) -> String {
+        self.error
+    }
+}
+
 #[marine]
 #[derive(Debug, Deserialize)]
 pub struct U32Value {
@@ -206,6 +241,184 @@ impl SpellValueT for U32Value {
     }
 }
 
+#[marine]
+#[derive(Debug, Deserialize)]
+pub struct I64Value {
+    pub value: i64,
+    pub success: bool,
+    pub error: String,
+    pub absent: bool,
+}
+
+impl From<eyre::Result<Option<i64>>> for I64Value {
+    fn from(value: eyre::Result<Option<i64>>) -> Self {
+        match value {
+            Ok(Some(value)) => I64Value {
+                value,
+                success: true,
+                error: String::new(),
+                absent: false,
+            },
+            Ok(None) => I64Value {
+                value: i64::default(),
+                success: true,
+                error: String::new(),
+                absent: true,
+            },
+            Err(e) => I64Value {
+                value: i64::default(),
+                success: false,
+                error: format_error(e),
+                absent: false,
+            },
+        }
+    }
+}
+
+impl SpellValueT for I64Value {
+    fn is_success(&self) -> bool {
+        self.success
+    }
+
+    fn take_error(self) -> String {
+        self.error
+    }
+}
+
+#[marine]
+#[derive(Debug, Deserialize)]
+pub struct F64Value {
+    pub value: f64,
+    pub success: bool,
+    pub error: String,
+    pub absent: bool,
+}
+
+impl From<eyre::Result<Option<f64>>> for F64Value {
+    fn from(value: eyre::Result<Option<f64>>) -> Self {
+        match value {
+            Ok(Some(value)) => F64Value {
+                value,
+                success: true,
+                error: String::new(),
+                absent: false,
+            },
+            Ok(None) => F64Value {
+                value: f64::default(),
+                success: true,
+                error: String::new(),
+                absent: true,
+            },
+            Err(e) => F64Value {
+                value: f64::default(),
+                success: false,
+                error: format_error(e),
+                absent: false,
+            },
+        }
+    }
+}
+
+impl SpellValueT for F64Value {
+    fn is_success(&self) -> bool {
+        self.success
+    }
+
+    fn take_error(self) -> String {
+        self.error
+    }
+}
+
+#[marine]
+#[derive(Debug, Deserialize)]
+/// Unlike `BoolValue`, which is used to report facts like key existence,
+/// this one represents a stored boolean that may be absent.
+pub struct OptBoolValue {
+    pub value: bool,
+    pub success: bool,
+    pub error: String,
+    pub absent: bool,
+}
+
+impl From<eyre::Result<Option<bool>>> for OptBoolValue {
+    fn from(value: eyre::Result<Option<bool>>) -> Self {
+        match value {
+            Ok(Some(value)) => OptBoolValue {
+                value,
+                success: true,
+                error: String::new(),
+                absent: false,
+            },
+            Ok(None) => OptBoolValue {
+                value: false,
+                success: true,
+                error: String::new(),
+                absent: true,
+            },
+            Err(e) => OptBoolValue {
+                value: false,
+                success: false,
+                error: format_error(e),
+                absent: false,
+            },
+        }
+    }
+}
+
+impl SpellValueT for OptBoolValue {
+    fn is_success(&self) -> bool {
+        self.success
+    }
+
+    fn take_error(self) -> String {
+        self.error
+    }
+}
+
+#[marine]
+#[derive(Debug, Deserialize)]
+pub struct BytesValue {
+    pub value: Vec<u8>,
+    pub success: bool,
+    pub error: String,
+    pub absent: bool,
+}
+
+impl From<eyre::Result<Option<Vec<u8>>>> for BytesValue {
+    fn from(value: eyre::Result<Option<Vec<u8>>>) -> Self {
+        match value {
+            Ok(Some(value)) => BytesValue {
+                value,
+                success: true,
+                error: String::new(),
+                absent: false,
+            },
+            Ok(None) => BytesValue {
+                value: vec![],
+                success: true,
+                error: String::new(),
+                absent: true,
+            },
+            Err(e) => BytesValue {
+                value: vec![],
+                success: false,
+                error: format_error(e),
+                absent: false,
+            },
+        }
+    }
+}
+
+impl SpellValueT for BytesValue {
+    fn is_success(&self) -> bool {
+        self.success
+    }
+
+    fn take_error(self) -> String {
+        self.error
+    }
+}
+
 #[marine]
 #[derive(Debug, Deserialize)]
 pub struct ScriptValue {
@@ -283,6 +496,8 @@ pub struct MailboxMessage {
     pub init_peer_id: String,
     pub timestamp: u64,
     pub message: String,
+    /// grows with every pushed message, see `mailbox_ack`
+    pub id: u64,
 }
 
 impl MailboxMessage {
@@ -297,6 +512,9 @@ impl MailboxMessage {
             message: statement
                 .read::<String>(2)
                 .context("failed to read `message` field")?,
+            id: statement
+                .read::<i64>(3)
+                .context("failed to read `id` field")? as u64,
         })
     }
 }
@@ -304,7 +522,7 @@ impl MailboxMessage {
 #[marine]
 #[derive(Debug)]
 /// `messages` contains up to `DEFAULT_MAX_MAILBOX` latest messages,
-/// sorted in the order they were pushed
+/// the latest first for `get_mailbox` and the oldest first for `mailbox_peek`
 pub struct GetMailboxResult {
     pub messages: Vec<MailboxMessage>,
     pub success: bool,
@@ -364,10 +582,92 @@ impl From<eyre::Result<Option<MailboxMessage>>> for PopMailboxResult {
 }
 
 #[marine]
-#[derive(Debug)]
+#[derive(Default, Debug)]
 pub struct Log {
+    /// grows with every stored log, see `get_logs_since`
+    pub id: u64,
     pub timestamp: u64,
     pub message: String,
+    /// one of `trace`, `debug`, `info`, `warn` or `error`
+    pub level: String,
+    pub tags: Vec<String>,
+    /// the particle that stored the log, empty for the logs stored before it was recorded
+    pub particle_id: String,
+    pub particle_timestamp: u64,
+}
+
+#[marine]
+#[derive(Default, Clone, Debug, Deserialize)]
+/// A top-level field of a structured log and the JSON text it must be equal to, e.g. `"ok"` or `42`
+pub struct LogFieldValue {
+    pub field: String,
+    pub value: String,
+}
+
+#[marine]
+#[derive(Default, Clone, Debug, Deserialize)]
+/// Conditions for `query_logs`, a log must match all of them
+pub struct LogQuery {
+    /// top-level fields the log must have
+    pub exists: Vec<String>,
+    pub equals: Vec<LogFieldValue>,
+}
+
+#[marine]
+#[derive(Debug)]
+/// A page of logs. `cursor` continues the reading when passed to the next call:
+/// it's the id of the last returned log for `get_logs_since` and `get_logs_since_timestamp`
+/// and the next offset for `get_logs_page`.
+/// `has_more` is true if there are more logs after this page.
+pub struct LogsPage {
+    pub logs: Vec<Log>,
+    pub cursor: u64,
+    pub has_more: bool,
+    pub success: bool,
+    pub error: String,
+}
+
+impl From<eyre::Result<(Vec<Log>, u64, bool)>> for LogsPage {
+    fn from(result: eyre::Result<(Vec<Log>, u64, bool)>) -> Self {
+        match result {
+            Ok((logs, cursor, has_more)) => LogsPage {
+                logs,
+                cursor,
+                has_more,
+                success: true,
+                error: String::new(),
+            },
+            Err(e) => LogsPage {
+                logs: vec![],
+                cursor: 0,
+                has_more: false,
+                success: false,
+                error: format_error(e),
+            },
+        }
+    }
+}
+
+impl SpellValueT for LogsPage {
+    fn is_success(&self) -> bool {
+        self.success
+    }
+
+    fn take_error(self) -> String {
+        self.error
+    }
+}
+
+#[marine]
+#[derive(Default, Clone, Debug, Deserialize)]
+/// Conditions for `get_logs_filtered`, empty strings and zero timestamps match any log
+pub struct LogFilter {
+    /// the least severe level to return, e.g. `warn` returns `warn` and `error` logs
+    pub min_level: String,
+    pub tag: String,
+    /// unix timestamps in seconds, both ends are included
+    pub from_timestamp: u64,
+    pub to_timestamp: u64,
 }
 
 #[marine]
@@ -396,3 +696,188 @@ impl From<eyre::Result<Vec<Log>>> for GetLogsResult {
         }
     }
 }
+
+#[marine]
+#[derive(Debug, Clone, Deserialize)]
+/// A single write operation of a `kv_batch` call.
+/// `op` is one of `set_string`, `set_u32`, `remove_key` or `list_push_string`.
+/// `string_value` is used by `set_string` and `list_push_string`, `u32_value` by `set_u32`.
+pub struct KvOp {
+    pub op: String,
+    pub key: String,
+    pub string_value: String,
+    pub u32_value: u32,
+}
+
+#[marine]
+#[derive(Debug)]
+/// `results` contains a result for every operation of the batch, in the same order.
+/// If any of the operations fails, nothing is written and `results` is empty.
+pub struct KvBatchResult {
+    pub results: Vec<UnitValue>,
+    pub success: bool,
+    pub error: String,
+}
+
+impl From<eyre::Result<Vec<UnitValue>>> for KvBatchResult {
+    fn from(result: eyre::Result<Vec<UnitValue>>) -> Self {
+        match result {
+            Ok(results) => KvBatchResult {
+                results,
+                success: true,
+                error: String::new(),
+            },
+            Err(e) => KvBatchResult {
+                results: vec![],
+                success: false,
+                error: format_error(e),
+            },
+        }
+    }
+}
+
+impl SpellValueT for KvBatchResult {
+    fn is_success(&self) -> bool {
+        self.success
+    }
+
+    fn take_error(self) -> String {
+        self.error
+    }
+}
+
+#[marine]
+#[derive(Debug, Clone, Deserialize)]
+/// `value_type` is one of `string`, `json`, `u32`, `i64`, `f64`, `bool`, `bytes`, `list` or `map`
+pub struct KeyInfo {
+    pub key: String,
+    pub value_type: String,
+}
+
+#[marine]
+#[derive(Debug)]
+/// A page of keys sorted by name. `has_more` is true if there are more keys after this page.
+pub struct KeysValue {
+    pub keys: Vec<KeyInfo>,
+    pub has_more: bool,
+    pub success: bool,
+    pub error: String,
+}
+
+impl From<eyre::Result<(Vec<KeyInfo>, bool)>> for KeysValue {
+    fn from(result: eyre::Result<(Vec<KeyInfo>, bool)>) -> Self {
+        match result {
+            Ok((keys, has_more)) => KeysValue {
+                keys,
+                has_more,
+                success: true,
+                error: String::new(),
+            },
+            Err(e) => KeysValue {
+                keys: vec![],
+                has_more: false,
+                success: false,
+                error: format_error(e),
+            },
+        }
+    }
+}
+
+impl SpellValueT for KeysValue {
+    fn is_success(&self) -> bool {
+        self.success
+    }
+
+    fn take_error(self) -> String {
+        self.error
+    }
+}
+
+#[marine]
+#[derive(Debug, Clone, Deserialize)]
+pub struct KeyString {
+    pub key: String,
+    pub value: String,
+}
+
+#[marine]
+#[derive(Debug)]
+/// A page of string values sorted by key. `has_more` is true if there are more values after this page.
+pub struct KeyStringsValue {
+    pub entries: Vec<KeyString>,
+    pub has_more: bool,
+    pub success: bool,
+    pub error: String,
+}
+
+impl From<eyre::Result<(Vec<KeyString>, bool)>> for KeyStringsValue {
+    fn from(result: eyre::Result<(Vec<KeyString>, bool)>) -> Self {
+        match result {
+            Ok((entries, has_more)) => KeyStringsValue {
+                entries,
+                has_more,
+                success: true,
+                error: String::new(),
+            },
+            Err(e) => KeyStringsValue {
+                entries: vec![],
+                has_more: false,
+                success: false,
+                error: format_error(e),
+            },
+        }
+    }
+}
+
+impl SpellValueT for KeyStringsValue {
+    fn is_success(&self) -> bool {
+        self.success
+    }
+
+    fn take_error(self) -> String {
+        self.error
+    }
+}
+
+#[marine]
+#[derive(Debug, Clone, Deserialize)]
+pub struct MapEntry {
+    pub field: String,
+    pub value: String,
+}
+
+#[marine]
+#[derive(Debug)]
+/// All fields of a map value sorted by field name
+pub struct MapValue {
+    pub entries: Vec<MapEntry>,
+    pub success: bool,
+    pub error: String,
+}
+
+impl From<eyre::Result<Vec<MapEntry>>> for MapValue {
+    fn from(result: eyre::Result<Vec<MapEntry>>) -> Self {
+        match result {
+            Ok(entries) => MapValue {
+                entries,
+                success: true,
+                error: String::new(),
+            },
+            Err(e) => MapValue {
+                entries: vec![],
+                success: false,
+                error: format_error(e),
+            },
+        }
+    }
+}
+
+impl SpellValueT for MapValue {
+    fn is_success(&self) -> bool {
+        self.success
+    }
+
+    fn take_error(self) -> String {
+        self.error
+    }
+}
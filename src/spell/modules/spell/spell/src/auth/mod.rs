@@ -24,11 +24,32 @@ pub use self::roles::{is_by_creator, is_by_spell};
 
 use crate::auth::keys::parse_permission;
 use crate::auth::roles::{authenticate, Role};
+use crate::quota::{guard_kv_quota, KvSlot};
+use crate::schema::db;
 use marine_rs_sdk::CallParameters;
 
-pub fn guard_kv_write(key: &str) -> eyre::Result<()> {
+/// Checks that the caller can write to the `key` and that the write fits the KV quotas.
+/// `value_size` is the number of bytes the write adds, writes that only remove data pass `None`
+/// and aren't checked against the quotas.
+pub fn guard_kv_write(key: &str, value_size: Option<usize>) -> eyre::Result<()> {
     let cp = marine_rs_sdk::get_call_parameters();
-    check_kv_write(key, &cp)
+    check_kv_write(key, &cp)?;
+    if let Some(value_size) = value_size {
+        guard_kv_quota(&db(), key, KvSlot::Value, value_size)?;
+    }
+
+    Ok(())
+}
+
+/// returns true if function is called by the host
+pub fn is_by_host() -> bool {
+    let cp = marine_rs_sdk::get_call_parameters();
+    authenticate(&cp) == Some(Role::Host)
+}
+
+/// returns true if function is called by the spell itself, its worker or its host
+pub fn is_by_trusted_peer(call_parameters: &CallParameters) -> bool {
+    authenticate(call_parameters).is_some()
 }
 
 pub fn is_kv_write_permitted(key: &str, call_parameters: &CallParameters) -> bool {
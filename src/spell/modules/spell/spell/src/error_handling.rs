@@ -22,16 +22,24 @@ use std::convert::TryFrom;
 
 use eyre::WrapErr;
 use marine_rs_sdk::marine;
-use marine_sqlite_connector::{State, Statement};
+use marine_sqlite_connector::{Connection, State, Statement};
 
 use fluence_spell_dtos::value::UnitValue;
 
-use crate::auth::is_by_spell;
+use crate::auth::{is_by_creator, is_by_spell};
+use crate::misc::{fetch_rows, in_transaction, read_config, write_config};
 use crate::schema::db;
 
+/// Columns read by `LastErrorEntry::try_from`
+pub const ERROR_COLUMNS: &str =
+    "particle_id, timestamp, error_idx, error_code, instruction, message, peer_id";
+/// Unix time in seconds when an error was stored.
+/// Errors stored before `stored_at` was added only have the particle timestamp in milliseconds.
+pub const ERROR_STORED_AT: &str = "COALESCE(stored_at, timestamp / 1000)";
+
 /// The `%last_error%` content.
 #[marine]
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
 pub struct LastError {
     /// The error code.
     pub error_code: u32,
@@ -44,7 +52,7 @@ pub struct LastError {
 }
 
 #[marine]
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
 pub struct LastErrorEntry {
     /// The reported error.
     pub last_error: LastError,
@@ -101,6 +109,158 @@ pub struct AllErrorsResult {
 }
 
 #[marine]
+#[derive(Default, Clone, Debug)]
+pub struct StoredError {
+    pub particle_id: String,
+    /// The unix time in seconds when the error was stored.
+    pub timestamp: u64,
+    /// The particle timestamp in milliseconds.
+    pub particle_timestamp: u64,
+    pub error: LastErrorEntry,
+}
+
+#[marine]
+pub struct QueryErrorsResult {
+    pub errors: Vec<StoredError>,
+    pub success: bool,
+    pub error: String,
+}
+
+#[marine]
+#[derive(Default, Clone, Debug)]
+pub struct ErrorCodeCount {
+    pub error_code: u32,
+    pub count: u64,
+}
+
+#[marine]
+pub struct ErrorCountsResult {
+    /// The most frequent codes first.
+    pub counts: Vec<ErrorCodeCount>,
+    pub success: bool,
+    pub error: String,
+}
+
+#[marine]
+#[derive(Default, Clone, Debug)]
+/// Occurrences of the same error, the timestamps are unix time in seconds.
+pub struct ErrorSummary {
+    pub last_error: LastError,
+    pub first_seen: u64,
+    pub last_seen: u64,
+    pub occurrences: u64,
+}
+
+#[marine]
+pub struct ErrorSummaryResult {
+    /// The most recently seen errors first.
+    pub errors: Vec<ErrorSummary>,
+    pub success: bool,
+    pub error: String,
+}
+
+fn is_dedup_enabled(conn: &Connection) -> eyre::Result<bool> {
+    Ok(read_config(conn, "dedup_errors")? != 0)
+}
+
+/// Counts the error as an occurrence of the latest stored error with the same code, instruction,
+/// message and peer id in any particle and moves that particle to the top of the LRU.
+/// The current particle isn't registered, so repeated errors of every tick don't evict other errors.
+/// Returns false if there is no such error.
+fn count_occurrence(
+    conn: &Connection,
+    error: &LastError,
+    particle_timestamp: u64,
+) -> eyre::Result<bool> {
+    let mut statement = conn.prepare(
+        r#"
+        SELECT rowid, particle_id FROM errors
+         WHERE error_code = ? AND instruction = ? AND message = ? AND peer_id = ?
+      ORDER BY rowid DESC
+         LIMIT 1
+        "#,
+    )?;
+    statement.bind(1, error.error_code as i64)?;
+    statement.bind(2, error.instruction.as_str())?;
+    statement.bind(3, error.message.as_str())?;
+    statement.bind(4, error.peer_id.as_str())?;
+    if statement.next()? != State::Row {
+        return Ok(false);
+    }
+    let rowid = statement.read::<i64>(0)?;
+    let particle_id = statement.read::<String>(1)?;
+
+    let mut statement = conn.prepare(
+        r#"
+        UPDATE errors
+           SET last_seen = strftime('%s', 'now'), occurrences = occurrences + 1
+         WHERE rowid = ?
+        "#,
+    )?;
+    statement.bind(1, rowid)?;
+    statement.next()?;
+
+    let mut statement = conn.prepare("UPDATE particles SET timestamp = ? WHERE particle_id = ?")?;
+    statement.bind(1, particle_timestamp as i64)?;
+    statement.bind(2, particle_id.as_str())?;
+    statement.next()?;
+
+    Ok(true)
+}
+
+/// Deletes the errors last seen more than `max_error_age_sec` ago if it's set, see `set_limits`.
+/// Particles left without errors are deleted as well, the triggers keep `count_particles` in sync.
+/// It's done when errors are stored, the getters skip expired errors with `not_expired` instead.
+pub fn expire_errors(conn: &Connection) -> eyre::Result<()> {
+    let max_age = read_config(conn, "max_error_age_sec")?;
+    if max_age == 0 {
+        return Ok(());
+    }
+
+    conn.execute(f!(r#"
+        DELETE FROM errors
+         WHERE last_seen < CAST(strftime('%s', 'now') AS INTEGER) - {max_age};
+        DELETE FROM particles WHERE particle_id NOT IN (SELECT particle_id FROM errors);
+        "#))?;
+
+    Ok(())
+}
+
+/// Condition matching the errors that `expire_errors` keeps
+pub fn not_expired(conn: &Connection) -> eyre::Result<String> {
+    let max_age = read_config(conn, "max_error_age_sec")?;
+    if max_age == 0 {
+        return Ok("1".to_string());
+    }
+
+    Ok(f!("last_seen >= CAST(strftime('%s', 'now') AS INTEGER) - {max_age}"))
+}
+
+/// Conditions on the time the errors were stored, zero timestamps match any time.
+fn time_conditions(since_ts: u64, until_ts: u64) -> Vec<String> {
+    let mut conditions = vec![];
+    if since_ts > 0 {
+        conditions.push(f!("{ERROR_STORED_AT} >= {since_ts}"));
+    }
+    if until_ts > 0 {
+        conditions.push(f!("{ERROR_STORED_AT} <= {until_ts}"));
+    }
+    conditions
+}
+
+fn where_clause(conditions: &[String]) -> String {
+    if conditions.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", conditions.join(" AND "))
+    }
+}
+
+#[marine]
+/// Store the error of the current particle.
+/// If the deduplication is on, see `set_error_deduplication`, an error with the same code,
+/// instruction, message and peer id as an already stored one, even in another particle,
+/// is only counted in `get_error_summary` and isn't stored for the current particle.
 pub fn store_error(error: LastError, error_idx: u32, particle_timestamp: u64) -> UnitValue {
     let call_parameters = marine_rs_sdk::get_call_parameters();
 
@@ -111,23 +271,32 @@ pub fn store_error(error: LastError, error_idx: u32, particle_timestamp: u64) ->
 
     let result: eyre::Result<()> = try {
         let conn = db();
-        let mut statement = conn.prepare(
-            r#"
-        INSERT INTO errors
-            (particle_id, timestamp, error_idx, error_code, instruction, message, peer_id)
-        VALUES
-            (?, ?, ?, ?, ?, ?, ?)
-        "#,
-        )?;
-        statement.bind(1, call_parameters.particle.id.as_str())?;
-        statement.bind(2, particle_timestamp as i64)?;
-        statement.bind(3, error_idx as i64)?;
-        statement.bind(4, error.error_code as i64)?;
-        statement.bind(5, error.instruction.as_str())?;
-        statement.bind(6, error.message.as_str())?;
-        statement.bind(7, error.peer_id.as_str())?;
+        expire_errors(&conn)?;
+        in_transaction(&conn, |conn| {
+            if is_dedup_enabled(conn)? && count_occurrence(conn, &error, particle_timestamp)? {
+                return Ok(());
+            }
 
-        statement.next()?;
+            let mut statement = conn.prepare(
+                r#"
+            INSERT INTO errors
+                (particle_id, timestamp, error_idx, error_code, instruction, message, peer_id,
+                 stored_at, last_seen)
+            VALUES
+                (?, ?, ?, ?, ?, ?, ?, strftime('%s', 'now'), strftime('%s', 'now'))
+            "#,
+            )?;
+            statement.bind(1, call_parameters.particle.id.as_str())?;
+            statement.bind(2, particle_timestamp as i64)?;
+            statement.bind(3, error_idx as i64)?;
+            statement.bind(4, error.error_code as i64)?;
+            statement.bind(5, error.instruction.as_str())?;
+            statement.bind(6, error.message.as_str())?;
+            statement.bind(7, error.peer_id.as_str())?;
+
+            statement.next()?;
+            Ok(())
+        })?
     };
 
     match result {
@@ -140,14 +309,15 @@ pub fn store_error(error: LastError, error_idx: u32, particle_timestamp: u64) ->
 pub fn get_errors(particle_id: String) -> Vec<LastErrorEntry> {
     let result: eyre::Result<Vec<LastErrorEntry>> = try {
         let conn = db();
-        let mut statement = conn.prepare(
+        let not_expired = not_expired(&conn)?;
+        let mut statement = conn.prepare(f!(
             r#"
             SELECT
                 *
             FROM
-                errors WHERE particle_id = ?
-        "#,
-        )?;
+                errors WHERE particle_id = ? AND {not_expired}
+        "#
+        ))?;
         statement.bind(1, particle_id.as_str())?;
         std::iter::from_fn(move || {
             let r: eyre::Result<Option<LastErrorEntry>> = try {
@@ -171,7 +341,8 @@ pub fn get_errors(particle_id: String) -> Vec<LastErrorEntry> {
 pub fn get_all_errors() -> AllErrorsResult {
     let result: eyre::Result<Vec<ParticleErrors>> = try {
         let conn = db();
-        let mut statement = conn.prepare(r#"SELECT * FROM errors"#)?;
+        let not_expired = not_expired(&conn)?;
+        let mut statement = conn.prepare(f!(r#"SELECT * FROM errors WHERE {not_expired}"#))?;
         std::iter::from_fn(move || {
             let r: eyre::Result<Option<(String, LastErrorEntry)>> = try {
                 if let State::Row = statement.next()? {
@@ -215,6 +386,217 @@ pub fn get_all_errors() -> AllErrorsResult {
     }
 }
 
+#[marine]
+/// Delete the errors of the particle, e.g. to acknowledge them.
+/// Can be called only by the spell or its creator.
+pub fn clear_errors(particle_id: String) -> UnitValue {
+    if !is_by_creator() {
+        return UnitValue::error("clear_errors can be called only by the spell or its creator");
+    }
+
+    let result: eyre::Result<()> = try {
+        let conn = db();
+        // the `clear_errors` trigger deletes the errors and keeps `count_particles` in sync
+        let mut statement = conn.prepare("DELETE FROM particles WHERE particle_id = ?")?;
+        statement.bind(1, particle_id.as_str())?;
+        statement.next()?;
+    };
+
+    match result {
+        Ok(_) => UnitValue::ok(),
+        Err(e) => UnitValue::error(format!("Error clearing errors: {}", e)),
+    }
+}
+
+#[marine]
+/// Delete all the stored errors. Can be called only by the spell or its creator.
+pub fn clear_all_errors() -> UnitValue {
+    if !is_by_creator() {
+        return UnitValue::error("clear_all_errors can be called only by the spell or its creator");
+    }
+
+    let result: eyre::Result<()> = try {
+        let conn = db();
+        in_transaction(&conn, |conn| {
+            conn.execute(
+                r#"
+                DELETE FROM particles;
+                DELETE FROM errors;
+                UPDATE config_table SET value = 0 WHERE parameter = 'count_particles';
+                "#,
+            )?;
+            Ok(())
+        })?
+    };
+
+    match result {
+        Ok(_) => UnitValue::ok(),
+        Err(e) => UnitValue::error(format!("Error clearing errors: {}", e)),
+    }
+}
+
+#[marine]
+/// Get up to `limit` errors matching the filters, the latest stored first.
+/// The timestamps are unix time in seconds, both ends are included.
+/// Zero timestamps, zero `error_code`, empty `instruction_substr` and zero `limit` match anything.
+pub fn query_errors(
+    since_ts: u64,
+    until_ts: u64,
+    error_code: u32,
+    instruction_substr: String,
+    limit: u32,
+) -> QueryErrorsResult {
+    let result: eyre::Result<Vec<StoredError>> = try {
+        let conn = db();
+        let mut conditions = time_conditions(since_ts, until_ts);
+        conditions.push(not_expired(&conn)?);
+        if error_code > 0 {
+            conditions.push(f!("error_code = {error_code}"));
+        }
+        if !instruction_substr.is_empty() {
+            conditions.push("instr(instruction, ?) > 0".to_string());
+        }
+        let filter_clause = where_clause(&conditions);
+        // a negative limit means no limit in sqlite
+        let limit = if limit > 0 { limit as i64 } else { -1 };
+
+        let mut statement = conn.prepare(f!(r#"
+            SELECT {ERROR_COLUMNS}, {ERROR_STORED_AT} AS stored
+              FROM errors
+              {filter_clause}
+          ORDER BY stored DESC, rowid DESC
+             LIMIT {limit}
+            "#))?;
+        if !instruction_substr.is_empty() {
+            statement.bind(1, instruction_substr.as_str())?;
+        }
+        fetch_rows(statement, |statement| {
+            Ok(Some(StoredError {
+                particle_id: statement.read::<String>(0)?,
+                timestamp: statement.read::<i64>(7)? as u64,
+                particle_timestamp: statement.read::<i64>(1)? as u64,
+                error: LastErrorEntry::try_from(statement)?,
+            }))
+        })
+    };
+
+    match result {
+        Ok(errors) => QueryErrorsResult {
+            errors,
+            success: true,
+            error: <_>::default(),
+        },
+        Err(err) => QueryErrorsResult {
+            errors: <_>::default(),
+            success: false,
+            error: format!("{:?}", err),
+        },
+    }
+}
+
+#[marine]
+/// Count the errors stored between the timestamps by their codes,
+/// including the deduplicated occurrences.
+/// The timestamps are unix time in seconds, both ends are included, zero timestamps match any time.
+pub fn count_errors_by_code(since_ts: u64, until_ts: u64) -> ErrorCountsResult {
+    let result: eyre::Result<Vec<ErrorCodeCount>> = try {
+        let conn = db();
+        let mut conditions = time_conditions(since_ts, until_ts);
+        conditions.push(not_expired(&conn)?);
+        let filter_clause = where_clause(&conditions);
+        let statement = conn.prepare(f!(r#"
+            SELECT error_code, SUM(occurrences) AS count
+              FROM errors
+              {filter_clause}
+          GROUP BY error_code
+          ORDER BY count DESC, error_code ASC
+            "#))?;
+        fetch_rows(statement, |statement| {
+            Ok(Some(ErrorCodeCount {
+                error_code: statement.read::<i64>(0)? as u32,
+                count: statement.read::<i64>(1)? as u64,
+            }))
+        })
+    };
+
+    match result {
+        Ok(counts) => ErrorCountsResult {
+            counts,
+            success: true,
+            error: <_>::default(),
+        },
+        Err(err) => ErrorCountsResult {
+            counts: <_>::default(),
+            success: false,
+            error: format!("{:?}", err),
+        },
+    }
+}
+
+#[marine]
+/// Turn the error deduplication on or off, see `store_error`.
+/// Can be called only by the spell or its creator.
+pub fn set_error_deduplication(enabled: bool) -> UnitValue {
+    if !is_by_creator() {
+        return UnitValue::error(
+            "set_error_deduplication can be called only by the spell or its creator",
+        );
+    }
+
+    let result: eyre::Result<()> = try {
+        write_config(&db(), "dedup_errors", enabled as u64)?;
+    };
+
+    match result {
+        Ok(_) => UnitValue::ok(),
+        Err(e) => UnitValue::error(format!("Error setting error deduplication: {}", e)),
+    }
+}
+
+#[marine]
+/// Get the stored errors grouped by code, instruction, message and peer id
+/// with the number of their occurrences, the most recently seen first.
+pub fn get_error_summary() -> ErrorSummaryResult {
+    let result: eyre::Result<Vec<ErrorSummary>> = try {
+        let conn = db();
+        let not_expired = not_expired(&conn)?;
+        let statement = conn.prepare(f!(r#"
+            SELECT error_code, instruction, message, peer_id,
+                   MIN({ERROR_STORED_AT}), MAX(last_seen) AS last, SUM(occurrences)
+              FROM errors
+             WHERE {not_expired}
+          GROUP BY error_code, instruction, message, peer_id
+          ORDER BY last DESC, MAX(rowid) DESC
+            "#))?;
+        fetch_rows(statement, |statement| {
+            Ok(Some(ErrorSummary {
+                last_error: LastError {
+                    error_code: statement.read::<i64>(0)? as u32,
+                    instruction: statement.read(1)?,
+                    message: statement.read(2)?,
+                    peer_id: statement.read(3)?,
+                },
+                first_seen: statement.read::<i64>(4)? as u64,
+                last_seen: statement.read::<i64>(5)? as u64,
+                occurrences: statement.read::<i64>(6)? as u64,
+            }))
+        })
+    };
+
+    match result {
+        Ok(errors) => ErrorSummaryResult {
+            errors,
+            success: true,
+            error: <_>::default(),
+        },
+        Err(err) => ErrorSummaryResult {
+            errors: <_>::default(),
+            success: false,
+            error: format!("{:?}", err),
+        },
+    }
+}
+
 #[test_env_helpers::after_each]
 #[cfg(test)]
 mod tests {
@@ -350,4 +732,211 @@ mod tests {
             assert_eq!(err.errors.len(), 2);
         }
     }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_query_errors(spell: marine_test_env::spell::ModuleInterface) {
+        use marine_test_env::spell::LastError;
+
+        let service_id = Uuid::new_v4();
+        let errors = [(1, "call peer"), (2, "ap"), (1, "call relay"), (3, "call peer")];
+        for (i, (error_code, instruction)) in errors.iter().enumerate() {
+            let particle_id = format!("spell_{}_{}", service_id, i);
+            let error = LastError {
+                error_code: *error_code,
+                instruction: instruction.to_string(),
+                message: format!("error {}", i),
+                peer_id: "peerid".to_string(),
+            };
+            let cp = cp(service_id.to_string(), particle_id);
+            let store = spell.store_error_cp(error, 0, 1000 * i as u64, cp);
+            assert!(store.success, "{}", store.error);
+        }
+
+        let all = spell.query_errors(0, 0, 0, "".to_string(), 0);
+        assert!(all.success, "query_errors failed: {}", all.error);
+        let messages: Vec<_> = all
+            .errors
+            .iter()
+            .map(|e| e.error.last_error.message.as_str())
+            .collect();
+        assert_eq!(messages, vec!["error 3", "error 2", "error 1", "error 0"]);
+        assert_eq!(all.errors[0].particle_id, format!("spell_{}_3", service_id));
+        assert_eq!(all.errors[0].particle_timestamp, 3000);
+
+        let filtered = spell.query_errors(0, 0, 1, "call".to_string(), 1);
+        assert!(filtered.success, "query_errors failed: {}", filtered.error);
+        assert_eq!(filtered.errors.len(), 1);
+        assert_eq!(filtered.errors[0].error.last_error.message, "error 2");
+
+        let now = all.errors[0].timestamp;
+        let recent = spell.query_errors(now, now + 3600, 0, "".to_string(), 0);
+        assert_eq!(recent.errors.len(), 4);
+        let future = spell.query_errors(now + 3600, 0, 0, "".to_string(), 0);
+        assert!(future.success, "query_errors failed: {}", future.error);
+        assert!(future.errors.is_empty());
+
+        let counts = spell.count_errors_by_code(0, 0);
+        assert!(counts.success, "count_errors_by_code failed: {}", counts.error);
+        let counts: Vec<_> = counts
+            .counts
+            .iter()
+            .map(|c| (c.error_code, c.count))
+            .collect();
+        assert_eq!(counts, vec![(1, 2), (2, 1), (3, 1)]);
+        assert!(spell.count_errors_by_code(now + 3600, 0).counts.is_empty());
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_clear_errors(spell: marine_test_env::spell::ModuleInterface) {
+        use marine_test_env::spell::LastError;
+
+        let service_id = Uuid::new_v4();
+        let error = LastError {
+            error_code: 1,
+            instruction: "(null)".to_string(),
+            message: "oh my god".to_string(),
+            peer_id: "peerid".to_string(),
+        };
+        for i in 0..3 {
+            let particle_id = format!("spell_{}_{}", service_id, i);
+            let cp = cp(service_id.to_string(), particle_id);
+            let store = spell.store_error_cp(error.clone(), 0, 123, cp);
+            assert!(store.success, "{}", store.error);
+        }
+
+        let mut other = cp(service_id.to_string(), "some-particle".to_string());
+        other.particle.init_peer_id = "other".to_string();
+        let particle_id = format!("spell_{}_0", service_id);
+        let clear = spell.clear_errors_cp(particle_id.clone(), other.clone());
+        assert!(!clear.success, "only the spell or its creator can clear errors");
+        let clear = spell.clear_all_errors_cp(other);
+        assert!(!clear.success, "only the spell or its creator can clear errors");
+
+        let creator = cp(service_id.to_string(), "some-particle".to_string());
+        let clear = spell.clear_errors_cp(particle_id.clone(), creator.clone());
+        assert!(clear.success, "clear_errors failed: {}", clear.error);
+        assert!(spell.get_errors(particle_id).is_empty());
+        assert_eq!(spell.get_all_errors().particle_errors.len(), 2);
+
+        let clear = spell.clear_all_errors_cp(creator);
+        assert!(clear.success, "clear_all_errors failed: {}", clear.error);
+        assert!(spell.get_all_errors().particle_errors.is_empty());
+
+        let stats = spell.get_storage_stats(0).stats;
+        let particles = stats.tables.iter().find(|t| t.table == "particles").unwrap();
+        assert_eq!(particles.counter, 0);
+        assert!(!particles.counter_drift);
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_error_max_age(spell: marine_test_env::spell::ModuleInterface) {
+        use marine_test_env::spell::{LastError, Limits};
+
+        let service_id = Uuid::new_v4();
+        let error = LastError {
+            error_code: 1,
+            instruction: "(null)".to_string(),
+            message: "oh my god".to_string(),
+            peer_id: "peerid".to_string(),
+        };
+        let old_particle = format!("spell_{}_0", service_id);
+        let cp_old = cp(service_id.to_string(), old_particle.clone());
+        let store = spell.store_error_cp(error.clone(), 0, 123, cp_old.clone());
+        assert!(store.success, "{}", store.error);
+
+        let limits = Limits {
+            max_logs: 10,
+            max_mailbox: 10,
+            max_particles: 10,
+            max_runs: 10,
+            max_error_age_sec: 1,
+        };
+        let set = spell.set_limits_cp(limits, cp_old);
+        assert!(set.success, "set_limits failed: {}", set.error);
+        assert_eq!(spell.get_limits().limits.max_error_age_sec, 1);
+
+        std::thread::sleep(std::time::Duration::from_secs(2));
+
+        // the getters skip the expired errors, they are deleted only when errors are stored
+        assert!(spell.get_errors(old_particle.clone()).is_empty());
+        assert!(spell.get_all_errors().particle_errors.is_empty());
+        let stats = spell.get_storage_stats(0).stats;
+        let errors = stats.tables.iter().find(|t| t.table == "errors").unwrap();
+        assert_eq!(errors.rows, 1);
+
+        let new_particle = format!("spell_{}_1", service_id);
+        let cp_new = cp(service_id.to_string(), new_particle.clone());
+        let store = spell.store_error_cp(error, 0, 456, cp_new);
+        assert!(store.success, "{}", store.error);
+
+        let errors = spell.get_all_errors().particle_errors;
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].particle_id, new_particle);
+        assert!(spell.get_errors(old_particle).is_empty());
+
+        let stats = spell.get_storage_stats(0).stats;
+        let particles = stats.tables.iter().find(|t| t.table == "particles").unwrap();
+        assert_eq!(particles.counter, 1);
+        assert!(!particles.counter_drift);
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_error_deduplication(spell: marine_test_env::spell::ModuleInterface) {
+        use marine_test_env::spell::LastError;
+
+        let service_id = Uuid::new_v4();
+        let repeated = LastError {
+            error_code: 1,
+            instruction: "call peer".to_string(),
+            message: "timeout".to_string(),
+            peer_id: "peerid".to_string(),
+        };
+        let other = LastError {
+            error_code: 2,
+            ..repeated.clone()
+        };
+
+        let mut dedup_cp = cp(service_id.to_string(), "some-particle".to_string());
+        dedup_cp.particle.init_peer_id = "other".to_string();
+        let set = spell.set_error_deduplication_cp(true, dedup_cp.clone());
+        assert!(!set.success, "only the spell or its creator can turn on deduplication");
+        dedup_cp.particle.init_peer_id = "folex".to_string();
+        let set = spell.set_error_deduplication_cp(true, dedup_cp);
+        assert!(set.success, "set_error_deduplication failed: {}", set.error);
+
+        for i in 0..5 {
+            let particle_id = format!("spell_{}_{}", service_id, i);
+            let cp = cp(service_id.to_string(), particle_id);
+            let store = spell.store_error_cp(repeated.clone(), 0, i, cp);
+            assert!(store.success, "{}", store.error);
+        }
+        let particle_id = format!("spell_{}_5", service_id);
+        let cp5 = cp(service_id.to_string(), particle_id.clone());
+        let store = spell.store_error_cp(other, 0, 5, cp5);
+        assert!(store.success, "{}", store.error);
+
+        // the repeated error is stored once, for the particle it was first seen in
+        let all = spell.get_all_errors().particle_errors;
+        assert_eq!(all.len(), 2);
+        let first = spell.get_errors(format!("spell_{}_0", service_id));
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].last_error.message, "timeout");
+        assert!(spell.get_errors(format!("spell_{}_4", service_id)).is_empty());
+        let stats = spell.get_storage_stats(0).stats;
+        let particles = stats.tables.iter().find(|t| t.table == "particles").unwrap();
+        assert_eq!(particles.rows, 2, "the repeating ticks must not be registered");
+
+        let summary = spell.get_error_summary();
+        assert!(summary.success, "get_error_summary failed: {}", summary.error);
+        let summary: Vec<_> = summary
+            .errors
+            .iter()
+            .map(|e| (e.last_error.error_code, e.occurrences))
+            .collect();
+        assert_eq!(summary, vec![(2, 1), (1, 5)]);
+
+        let counts = spell.count_errors_by_code(0, 0).counts;
+        let counts: Vec<_> = counts.iter().map(|c| (c.error_code, c.count)).collect();
+        assert_eq!(counts, vec![(1, 5), (2, 1)]);
+    }
 }
@@ -0,0 +1,208 @@
+/*
+ * Aqua Spell Service
+ *
+ * Copyright (C) 2024 Fluence DAO
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation version 3 of the
+ * License.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use eyre::Context;
+use marine_rs_sdk::marine;
+use marine_sqlite_connector::Connection;
+
+use crate::auth::guard_kv_write;
+use fluence_spell_dtos::value::{KvBatchResult, KvOp, UnitValue};
+
+use crate::kv::collection::push_string;
+use crate::kv::primitive::{delete_key, store_string, store_u32};
+use crate::misc::in_transaction;
+use crate::quota::check_kv_usage;
+use crate::schema::db;
+
+fn apply_op(conn: &Connection, op: &KvOp) -> eyre::Result<()> {
+    match op.op.as_str() {
+        "set_string" => store_string(conn, &op.key, &op.string_value),
+        "set_u32" => store_u32(conn, &op.key, op.u32_value),
+        "remove_key" => delete_key(conn, &op.key),
+        "list_push_string" => push_string(conn, &op.key, &op.string_value),
+        unknown => Err(eyre::eyre!("unknown operation `{unknown}`")),
+    }
+}
+
+/// Number of bytes the operation adds, `None` if it only removes data
+fn op_size(op: &KvOp) -> Option<usize> {
+    match op.op.as_str() {
+        "set_string" | "list_push_string" => Some(op.string_value.len()),
+        "set_u32" => Some(op.u32_value.to_string().len()),
+        _ => None,
+    }
+}
+
+#[marine]
+/// Apply all the operations in a single transaction: either all of them are written or none.
+/// Write permissions and quotas are checked for every key before anything is written,
+/// and the quotas are checked once more for the whole batch before it's committed.
+pub fn kv_batch(ops: Vec<KvOp>) -> KvBatchResult {
+    let result: eyre::Result<Vec<UnitValue>> = try {
+        for (idx, op) in ops.iter().enumerate() {
+            guard_kv_write(&op.key, op_size(op))
+                .context(format!("operation #{idx} is forbidden"))?;
+        }
+
+        let conn = db();
+        in_transaction(&conn, |conn| {
+            for (idx, op) in ops.iter().enumerate() {
+                apply_op(conn, op).context(format!("operation #{idx} `{}` failed", op.op))?;
+            }
+            check_kv_usage(conn)?;
+            Ok(ops.iter().map(|_| UnitValue::ok()).collect())
+        })?
+    };
+
+    result.into()
+}
+
+#[test_env_helpers::after_each]
+#[cfg(test)]
+mod tests {
+    use marine_rs_sdk::CallParameters;
+    use marine_rs_sdk::ParticleParameters;
+    use marine_rs_sdk_test::marine_test;
+
+    const DB_FILE: &str = "./tests_artifacts/spell.sqlite";
+
+    #[ctor::ctor]
+    /// usage of 'ctor' makes this function run only once
+    fn before_all_tests() {
+        std::fs::remove_file(DB_FILE).ok();
+    }
+
+    /// after_each macro copy-pastes this function into every test
+    fn after_each() {
+        std::fs::remove_file(DB_FILE).ok();
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_batch(spell: marine_test_env::spell::ModuleInterface) {
+        use marine_test_env::spell::KvOp;
+        let op = |op: &str, key: &str, string_value: &str, u32_value| KvOp {
+            op: op.to_string(),
+            key: key.to_string(),
+            string_value: string_value.to_string(),
+            u32_value,
+        };
+
+        let set = spell.set_string_cp("old".into(), "value".into(), spell_call_params());
+        assert!(set.success, "set_string failed: {}", set.error);
+
+        let ops = vec![
+            op("set_string", "str", "b", 0),
+            op("set_u32", "num", "", 42),
+            op("remove_key", "old", "", 0),
+            op("list_push_string", "list", "x", 0),
+            op("list_push_string", "list", "y", 0),
+        ];
+        let batch = spell.kv_batch_cp(ops, spell_call_params());
+        assert!(batch.success, "kv_batch failed: {}", batch.error);
+        assert_eq!(batch.results.len(), 5);
+        assert!(batch.results.iter().all(|r| r.success));
+
+        assert_eq!(spell.get_string("str".into()).value, "b");
+        assert_eq!(spell.get_u32("num".into()).value, 42);
+        assert!(!spell.exists("old".into()).value, "old key must be removed");
+        assert_eq!(spell.list_get_strings("list".into()).value, vec!["x", "y"]);
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_batch_forbidden_key(spell: marine_test_env::spell::ModuleInterface) {
+        use marine_test_env::spell::KvOp;
+        let op = |op: &str, key: &str, string_value: &str, u32_value| KvOp {
+            op: op.to_string(),
+            key: key.to_string(),
+            string_value: string_value.to_string(),
+            u32_value,
+        };
+
+        let ops = vec![
+            op("set_string", "w_str", "b", 0),
+            op("set_u32", "h_num", "", 42),
+        ];
+        let batch = spell.kv_batch_cp(ops, worker_call_params());
+        assert!(!batch.success, "kv_batch must fail on a forbidden key");
+        assert!(batch.results.is_empty());
+
+        assert!(
+            !spell.exists("w_str".into()).value,
+            "nothing must be written if any key is forbidden"
+        );
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_batch_rollback(spell: marine_test_env::spell::ModuleInterface) {
+        use marine_test_env::spell::KvOp;
+        let op = |op: &str, key: &str, string_value: &str, u32_value| KvOp {
+            op: op.to_string(),
+            key: key.to_string(),
+            string_value: string_value.to_string(),
+            u32_value,
+        };
+
+        let ops = vec![
+            op("set_string", "str", "b", 0),
+            op("list_push_string", "list", "x", 0),
+            op("unknown_op", "num", "", 0),
+        ];
+        let batch = spell.kv_batch_cp(ops, spell_call_params());
+        assert!(!batch.success, "kv_batch must fail on an unknown operation");
+
+        assert!(
+            !spell.exists("str".into()).value,
+            "batch must be rolled back"
+        );
+        assert!(
+            !spell.exists("list".into()).value,
+            "batch must be rolled back"
+        );
+    }
+
+    fn spell_call_params() -> CallParameters {
+        CallParameters {
+            particle: ParticleParameters {
+                init_peer_id: "worker-id".to_string(),
+                id: "spell_spell-id_0".to_string(),
+                ..<_>::default()
+            },
+            service_creator_peer_id: "worker-id".to_string(),
+            service_id: "spell-id".to_string(),
+            worker_id: "worker-id".to_string(),
+            host_id: "host-id".to_string(),
+            tetraplets: vec![],
+        }
+    }
+
+    fn worker_call_params() -> CallParameters {
+        CallParameters {
+            particle: ParticleParameters {
+                init_peer_id: "worker-id".to_string(),
+                id: "some-particle".to_string(),
+                ..<_>::default()
+            },
+            service_creator_peer_id: "worker-id".to_string(),
+            service_id: "spell-id".to_string(),
+            worker_id: "worker-id".to_string(),
+            host_id: "host-id".to_string(),
+            tetraplets: vec![],
+        }
+    }
+}
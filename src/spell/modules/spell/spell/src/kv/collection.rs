@@ -18,39 +18,77 @@
  */
 
 use crate::auth::guard_kv_write;
-use fluence_spell_dtos::value::{StringListValue, StringValue, UnitValue};
+use fluence_spell_dtos::value::{
+    BoolValue, StringListValue, StringValue, U32ListValue, U32Value, UnitValue,
+};
 use marine_rs_sdk::marine;
-use marine_sqlite_connector::State;
+use marine_sqlite_connector::{Connection, State};
 
-use crate::misc::fetch_rows;
+use crate::kv::primitive::read_string;
+use crate::misc::{fetch_rows, in_transaction};
+use crate::quota::{guard_kv_quota, KvSlot};
 use crate::schema::db;
 
+/// Fails if the list under `key` holds elements of the other type,
+/// so strings and u32 values never get mixed in one list.
+fn guard_list_type(conn: &Connection, key: &str, u32_list: bool) -> eyre::Result<()> {
+    let mut statement = conn.prepare(
+        r#"
+        SELECT 1 FROM kv
+         WHERE key = ?
+           AND list_order >= 0
+           AND value_type != ?
+         LIMIT 1
+        "#,
+    )?;
+    let (value_type, other) = if u32_list {
+        ("u32", "strings")
+    } else {
+        ("string", "u32 values")
+    };
+    statement.bind(1, key)?;
+    statement.bind(2, value_type)?;
+
+    if let State::Row = statement.next()? {
+        Err(eyre::eyre!("the `{key}` holds a list of {other}"))?;
+    }
+
+    Ok(())
+}
+
+pub fn push_string(conn: &Connection, key: &str, value: &str) -> eyre::Result<()> {
+    guard_list_type(conn, key, false)?;
+    let mut statement = conn.prepare(
+        r#"
+            INSERT INTO kv (key, string, value_type, list_order)
+                VALUES (
+                    ?,
+                    COALESCE(?, ''),
+                    'string',
+                    COALESCE(
+                        (
+                            SELECT MAX(list_order) + 1
+                            FROM kv
+                            WHERE key = ?
+                        ),
+                        0
+                    )
+                )
+        "#,
+    )?;
+    statement.bind(1, key)?;
+    statement.bind(2, value)?;
+    statement.bind(3, key)?;
+    statement.next()?;
+
+    Ok(())
+}
+
 #[marine]
 pub fn list_push_string(key: &str, value: String) -> UnitValue {
     let result: eyre::Result<()> = try {
-        guard_kv_write(key)?;
-        let conn = db();
-        let mut statement = conn.prepare(
-            r#"
-                INSERT INTO kv (key, string, list_order)
-                    VALUES (
-                        ?,
-                        ?,
-                        COALESCE(
-                            (
-                                SELECT MAX(list_order) + 1
-                                FROM kv
-                                WHERE key = ?
-                            ),
-                            0
-                        )
-                    )
-            "#,
-        )?;
-        statement.bind(1, key)?;
-        statement.bind(2, value.as_str())?;
-        statement.bind(3, key)?;
-        statement.next()?;
+        guard_kv_write(key, Some(value.len()))?;
+        in_transaction(&db(), |conn| push_string(conn, key, &value))?
     };
 
     result.into()
@@ -60,32 +98,35 @@ pub fn list_push_string(key: &str, value: String) -> UnitValue {
 /// Remove latest element in a list of strings, and return it
 pub fn list_pop_string(key: &str) -> StringValue {
     let result: eyre::Result<Option<String>> = try {
-        guard_kv_write(key)?;
-        let db = db();
-        let mut get = db.prepare(
-            r#"
-            SELECT string, max(list_order) FROM kv
-                WHERE key = ?
-                  AND list_order >= 0
-        "#,
-        )?;
-        get.bind(1, key)?;
-
-        let mut result = None;
-        if let State::Row = get.next()? {
-            let val = get.read::<String>(0)?;
-            if let Some(list_order) = get.read::<Option<i64>>(1)? {
-                let mut delete =
-                    db.prepare(r#"DELETE FROM kv WHERE key = ? AND list_order = ?"#)?;
-                delete.bind(1, key)?;
-                delete.bind(2, list_order)?;
-                delete.next()?;
-
-                result = Some(val.to_string());
-            }
-        };
-
-        result
+        guard_kv_write(key, None)?;
+        let conn = db();
+        in_transaction(&conn, |conn| {
+            guard_list_type(conn, key, false)?;
+            let mut get = conn.prepare(
+                r#"
+                SELECT string, max(list_order) FROM kv
+                    WHERE key = ?
+                      AND list_order >= 0
+            "#,
+            )?;
+            get.bind(1, key)?;
+
+            let mut result = None;
+            if let State::Row = get.next()? {
+                if let Some(list_order) = get.read::<Option<i64>>(1)? {
+                    let val = get.read::<String>(0)?;
+                    let mut delete =
+                        conn.prepare(r#"DELETE FROM kv WHERE key = ? AND list_order = ?"#)?;
+                    delete.bind(1, key)?;
+                    delete.bind(2, list_order)?;
+                    delete.next()?;
+
+                    result = Some(val);
+                }
+            };
+
+            Ok(result)
+        })?
     };
 
     result.into()
@@ -96,6 +137,7 @@ pub fn list_pop_string(key: &str) -> StringValue {
 pub fn list_get_strings(key: &str) -> StringListValue {
     let conn = db();
     let result: eyre::Result<Vec<String>> = try {
+        guard_list_type(&conn, key, false)?;
         let mut statement = conn.prepare(
             r#"
             SELECT
@@ -125,26 +167,450 @@ pub fn list_get_strings(key: &str) -> StringListValue {
 /// Returns an error on exceptions. Doesn't report if the value was actually removed.
 pub fn list_remove_string(key: &str, value: &str) -> UnitValue {
     let result: eyre::Result<()> = try {
-        guard_kv_write(key)?;
+        guard_kv_write(key, None)?;
         let conn = db();
         // list_order == -1 when the key isn't a part of a list
         let mut statement = conn.prepare(
             r#"
             DELETE FROM kv
                 WHERE key = ?
-                  AND (string = ? OR (string IS NULL AND ? IS NULL))
+                  AND string = COALESCE(?, '')
+                  AND value_type = 'string'
                   AND list_order != -1
         "#,
         )?;
         statement.bind(1, key)?;
         statement.bind(2, value)?;
-        statement.bind(3, value)?;
         statement.next()?;
     };
 
     result.into()
 }
 
+/// Returns `list_order` of the element at `index` counting from the head of the list
+fn list_order_at(conn: &Connection, key: &str, index: u32) -> eyre::Result<Option<i64>> {
+    let mut statement = conn.prepare(
+        r#"
+        SELECT list_order FROM kv
+         WHERE key = ?
+           AND list_order >= 0
+      ORDER BY list_order ASC
+         LIMIT 1 OFFSET ?
+        "#,
+    )?;
+    statement.bind(1, key)?;
+    statement.bind(2, index as i64)?;
+
+    if let State::Row = statement.next()? {
+        Ok(Some(statement.read::<i64>(0)?))
+    } else {
+        Ok(None)
+    }
+}
+
+fn count_list(conn: &Connection, key: &str) -> eyre::Result<u32> {
+    let mut statement =
+        conn.prepare("SELECT COUNT(*) FROM kv WHERE key = ? AND list_order >= 0")?;
+    statement.bind(1, key)?;
+    statement.next()?;
+
+    Ok(statement.read::<i64>(0)? as u32)
+}
+
+#[marine]
+/// Get the number of elements in a list of strings. Returns 0 for an absent list.
+pub fn list_len(key: &str) -> U32Value {
+    let result: eyre::Result<Option<u32>> = try {
+        let conn = db();
+        guard_list_type(&conn, key, false)?;
+        Some(count_list(&conn, key)?)
+    };
+
+    result.into()
+}
+
+#[marine]
+/// Get an element of a list of strings by its index from the head of the list.
+/// Returns `absent` when the index is out of bounds.
+pub fn list_get_at(key: &str, index: u32) -> StringValue {
+    let result: eyre::Result<Option<String>> = try {
+        let conn = db();
+        guard_list_type(&conn, key, false)?;
+        let mut statement = conn.prepare(
+            r#"
+            SELECT string FROM kv
+             WHERE key = ?
+               AND list_order >= 0
+          ORDER BY list_order ASC
+             LIMIT 1 OFFSET ?
+            "#,
+        )?;
+        statement.bind(1, key)?;
+        statement.bind(2, index as i64)?;
+        read_string(&mut statement, 0)?
+    };
+
+    result.into()
+}
+
+#[marine]
+/// Replace an element of a list of strings at the index from the head of the list.
+/// Fails when the index is out of bounds.
+pub fn list_set_at(key: &str, index: u32, value: &str) -> UnitValue {
+    let result: eyre::Result<()> = try {
+        guard_kv_write(key, None)?;
+        let conn = db();
+        in_transaction(&conn, |conn| {
+            guard_list_type(conn, key, false)?;
+            let list_order = list_order_at(conn, key, index)?
+                .ok_or_else(|| eyre::eyre!("index {index} is out of bounds of the list `{key}`"))?;
+            guard_kv_quota(conn, key, KvSlot::ListElement(list_order), value.len())?;
+
+            let mut update = conn.prepare(
+                "UPDATE kv SET string = COALESCE(?, '') WHERE key = ? AND list_order = ?",
+            )?;
+            update.bind(1, value)?;
+            update.bind(2, key)?;
+            update.bind(3, list_order)?;
+            update.next()?;
+
+            Ok(())
+        })?
+    };
+
+    result.into()
+}
+
+#[marine]
+/// Insert an element into a list of strings before the element at the index.
+/// `index` equal to the list length appends the element. Fails when the index is out of bounds.
+pub fn list_insert_at(key: &str, index: u32, value: &str) -> UnitValue {
+    let result: eyre::Result<()> = try {
+        guard_kv_write(key, Some(value.len()))?;
+        let conn = db();
+        in_transaction(&conn, |conn| {
+            guard_list_type(conn, key, false)?;
+            let list_order = match list_order_at(conn, key, index)? {
+                Some(list_order) => list_order,
+                None if index == count_list(conn, key)? => return push_string(conn, key, value),
+                None => Err(eyre::eyre!(
+                    "index {index} is out of bounds of the list `{key}`"
+                ))?,
+            };
+
+            // Move the tail one position forward. It's done in two steps through negative
+            // orders (always below -1), since a single UPDATE may hit the primary key constraint.
+            let mut shift = conn.prepare(
+                r#"
+                UPDATE kv SET list_order = -(list_order + 2)
+                 WHERE key = ? AND list_order >= ?
+                "#,
+            )?;
+            shift.bind(1, key)?;
+            shift.bind(2, list_order)?;
+            shift.next()?;
+
+            let mut restore = conn.prepare(
+                "UPDATE kv SET list_order = -list_order - 1 WHERE key = ? AND list_order < -1",
+            )?;
+            restore.bind(1, key)?;
+            restore.next()?;
+
+            let mut insert =
+                conn.prepare(
+                "INSERT INTO kv (key, string, value_type, list_order) VALUES (?, COALESCE(?, ''), 'string', ?)",
+            )?;
+            insert.bind(1, key)?;
+            insert.bind(2, value)?;
+            insert.bind(3, list_order)?;
+            insert.next()?;
+
+            Ok(())
+        })?
+    };
+
+    result.into()
+}
+
+#[marine]
+/// Get elements of a list of strings from `start` up to, but not including, `end`.
+/// Bounds are clamped to the list, so an out of bounds range gives an empty list.
+pub fn list_range(key: &str, start: u32, end: u32) -> StringListValue {
+    let conn = db();
+    let result: eyre::Result<Vec<String>> = try {
+        guard_list_type(&conn, key, false)?;
+        let mut statement = conn.prepare(
+            r#"
+            SELECT string FROM kv
+             WHERE key = ?
+               AND list_order >= 0
+          ORDER BY list_order ASC
+             LIMIT ? OFFSET ?
+            "#,
+        )?;
+        statement.bind(1, key)?;
+        statement.bind(2, end.saturating_sub(start) as i64)?;
+        statement.bind(3, start as i64)?;
+        fetch_rows(statement, |statement| {
+            let val = statement.read::<String>(0)?;
+            Ok(Some(val.to_string()))
+        })
+    };
+
+    result.into()
+}
+
+#[marine]
+/// Remove the first element in a list of strings, and return it
+pub fn list_shift(key: &str) -> StringValue {
+    let result: eyre::Result<Option<String>> = try {
+        guard_kv_write(key, None)?;
+        let conn = db();
+        in_transaction(&conn, |conn| {
+            guard_list_type(conn, key, false)?;
+            let mut get = conn.prepare(
+                r#"
+                SELECT string, list_order FROM kv
+                 WHERE key = ?
+                   AND list_order >= 0
+              ORDER BY list_order ASC
+                 LIMIT 1
+                "#,
+            )?;
+            get.bind(1, key)?;
+
+            if let State::Row = get.next()? {
+                let val = get.read::<String>(0)?;
+                let list_order = get.read::<i64>(1)?;
+
+                let mut delete = conn.prepare("DELETE FROM kv WHERE key = ? AND list_order = ?")?;
+                delete.bind(1, key)?;
+                delete.bind(2, list_order)?;
+                delete.next()?;
+
+                Ok(Some(val.to_string()))
+            } else {
+                Ok(None)
+            }
+        })?
+    };
+
+    result.into()
+}
+
+#[marine]
+/// Keep only the last `max_len` elements in a list of strings, removing the oldest ones.
+pub fn list_trim(key: &str, max_len: u32) -> UnitValue {
+    let result: eyre::Result<()> = try {
+        guard_kv_write(key, None)?;
+        let conn = db();
+        let mut statement = conn.prepare(
+            r#"
+            DELETE FROM kv
+             WHERE key = ?
+               AND list_order >= 0
+               AND list_order NOT IN (
+                   SELECT list_order FROM kv
+                    WHERE key = ?
+                      AND list_order >= 0
+                 ORDER BY list_order DESC
+                    LIMIT ?
+               )
+            "#,
+        )?;
+        statement.bind(1, key)?;
+        statement.bind(2, key)?;
+        statement.bind(3, max_len as i64)?;
+        statement.next()?;
+    };
+
+    result.into()
+}
+
+#[marine]
+pub fn list_push_u32(key: &str, value: u32) -> UnitValue {
+    let result: eyre::Result<()> = try {
+        guard_kv_write(key, Some(value.to_string().len()))?;
+        let conn = db();
+        in_transaction(&conn, |conn| {
+            guard_list_type(conn, key, true)?;
+            let mut statement = conn.prepare(
+                r#"
+                INSERT INTO kv (key, u32, value_type, list_order)
+                    VALUES (
+                        ?,
+                        ?,
+                        'u32',
+                        COALESCE((SELECT MAX(list_order) + 1 FROM kv WHERE key = ?), 0)
+                    )
+                "#,
+            )?;
+            statement.bind(1, key)?;
+            statement.bind(2, value as i64)?;
+            statement.bind(3, key)?;
+            statement.next()?;
+
+            Ok(())
+        })?
+    };
+
+    result.into()
+}
+
+#[marine]
+/// Remove latest element in a list of u32 values, and return it
+pub fn list_pop_u32(key: &str) -> U32Value {
+    let result: eyre::Result<Option<u32>> = try {
+        guard_kv_write(key, None)?;
+        let conn = db();
+        in_transaction(&conn, |conn| {
+            guard_list_type(conn, key, true)?;
+            let mut get = conn.prepare(
+                r#"
+                SELECT u32, max(list_order) FROM kv
+                    WHERE key = ?
+                      AND list_order >= 0
+                      AND value_type = 'u32'
+                "#,
+            )?;
+            get.bind(1, key)?;
+
+            let mut result = None;
+            if let State::Row = get.next()? {
+                if let Some(list_order) = get.read::<Option<i64>>(1)? {
+                    let value = get.read::<i64>(0)?;
+                    let mut delete =
+                        conn.prepare("DELETE FROM kv WHERE key = ? AND list_order = ?")?;
+                    delete.bind(1, key)?;
+                    delete.bind(2, list_order)?;
+                    delete.next()?;
+
+                    result = Some(value as u32);
+                }
+            }
+
+            Ok(result)
+        })?
+    };
+
+    result.into()
+}
+
+#[marine]
+/// Get a whole list of u32 values
+pub fn list_get_u32s(key: &str) -> U32ListValue {
+    let result: eyre::Result<Vec<u32>> = try {
+        let conn = db();
+        guard_list_type(&conn, key, true)?;
+        let mut statement = conn.prepare(
+            r#"
+            SELECT u32 FROM kv
+             WHERE key = ?
+               AND list_order >= 0
+               AND value_type = 'u32'
+          ORDER BY list_order ASC
+            "#,
+        )?;
+        statement.bind(1, key)?;
+        fetch_rows(statement, |statement| {
+            Ok(Some(statement.read::<i64>(0)? as u32))
+        })
+    };
+
+    result.into()
+}
+
+#[marine]
+/// Remove a value from a list of u32 values. If the value is in several places, remove all of them.
+pub fn list_remove_u32(key: &str, value: u32) -> UnitValue {
+    let result: eyre::Result<()> = try {
+        guard_kv_write(key, None)?;
+        let conn = db();
+        let mut statement =
+            conn.prepare("DELETE FROM kv WHERE key = ? AND u32 = ? AND list_order != -1")?;
+        statement.bind(1, key)?;
+        statement.bind(2, value as i64)?;
+        statement.next()?;
+    };
+
+    result.into()
+}
+
+fn set_has(conn: &Connection, key: &str, value: &str) -> eyre::Result<bool> {
+    let mut statement = conn.prepare(
+        r#"
+        SELECT 1 FROM kv
+         WHERE key = ?
+           AND string = COALESCE(?, '')
+           AND value_type = 'string'
+           AND list_order >= 0
+         LIMIT 1
+        "#,
+    )?;
+    statement.bind(1, key)?;
+    statement.bind(2, value)?;
+
+    Ok(matches!(statement.next()?, State::Row))
+}
+
+#[marine]
+/// Add a string to a set. Sets are lists of strings without duplicates.
+/// Returns `true` if the value was added and `false` if it was already in the set.
+pub fn set_add(key: &str, value: &str) -> BoolValue {
+    let result: eyre::Result<bool> = try {
+        guard_kv_write(key, Some(value.len()))?;
+        let conn = db();
+        in_transaction(&conn, |conn| {
+            if set_has(conn, key, value)? {
+                return Ok(false);
+            }
+            push_string(conn, key, value)?;
+            Ok(true)
+        })?
+    };
+
+    result.into()
+}
+
+#[marine]
+/// Remove a string from a set.
+/// Returns `true` if the value was removed and `false` if it wasn't in the set.
+pub fn set_remove(key: &str, value: &str) -> BoolValue {
+    let result: eyre::Result<bool> = try {
+        guard_kv_write(key, None)?;
+        let conn = db();
+        let mut statement = conn.prepare(
+            r#"
+            DELETE FROM kv
+                WHERE key = ?
+                  AND string = COALESCE(?, '')
+                  AND value_type = 'string'
+                  AND list_order >= 0
+            "#,
+        )?;
+        statement.bind(1, key)?;
+        statement.bind(2, value)?;
+        statement.next()?;
+
+        conn.changes() > 0
+    };
+
+    result.into()
+}
+
+#[marine]
+pub fn set_contains(key: &str, value: &str) -> BoolValue {
+    let result: eyre::Result<bool> = try { set_has(&db(), key, value)? };
+
+    result.into()
+}
+
+#[marine]
+/// Get all members of a set in the order they were added
+pub fn set_members(key: &str) -> StringListValue {
+    list_get_strings(key)
+}
+
 #[test_env_helpers::after_each]
 #[cfg(test)]
 mod tests {
@@ -384,6 +850,261 @@ mod tests {
         assert_eq!(list.value, vec![val1, val2]);
     }
 
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_list_index(spell: marine_test_env::spell::ModuleInterface) {
+        let key = "a";
+        for value in ["b", "f", "в"] {
+            let push = spell.list_push_string_cp(key.into(), value.into(), spell_call_params());
+            assert!(push.success, "push failed {}", push.error);
+        }
+        // make a gap in list orders
+        let _ = spell.list_remove_string_cp(key.into(), "f".into(), spell_call_params());
+
+        let len = spell.list_len(key.into());
+        assert!(len.success, "list_len failed {}", len.error);
+        assert_eq!(len.value, 2);
+        let len = spell.list_len("absent".into());
+        assert!(len.success, "list_len failed {}", len.error);
+        assert_eq!(len.value, 0, "absent list must be empty");
+
+        let get = spell.list_get_at(key.into(), 1);
+        assert!(get.success, "list_get_at failed {}", get.error);
+        assert_eq!(get.value, "в");
+        let get = spell.list_get_at(key.into(), 2);
+        assert!(get.success, "list_get_at failed {}", get.error);
+        assert!(get.absent, "out of bounds element must be absent");
+
+        let set = spell.list_set_at_cp(key.into(), 0, "x".into(), spell_call_params());
+        assert!(set.success, "list_set_at failed {}", set.error);
+        let set = spell.list_set_at_cp(key.into(), 2, "y".into(), spell_call_params());
+        assert!(!set.success, "list_set_at must fail out of bounds");
+
+        let get = spell.list_get_strings(key.into());
+        assert_eq!(get.value, vec!["x", "в"]);
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_list_insert_at(spell: marine_test_env::spell::ModuleInterface) {
+        let key = "a";
+        type SPELL = marine_test_env::spell::ModuleInterface;
+
+        let insert = |spell: &mut SPELL, index: u32, value: &str| {
+            spell.list_insert_at_cp(key.into(), index, value.into(), spell_call_params())
+        };
+
+        let inserted = insert(&mut spell, 0, "c");
+        assert!(
+            inserted.success,
+            "insert into empty list failed {}",
+            inserted.error
+        );
+        let inserted = insert(&mut spell, 0, "a");
+        assert!(inserted.success, "insert at head failed {}", inserted.error);
+        let inserted = insert(&mut spell, 1, "b");
+        assert!(
+            inserted.success,
+            "insert in the middle failed {}",
+            inserted.error
+        );
+        let inserted = insert(&mut spell, 3, "d");
+        assert!(inserted.success, "insert at tail failed {}", inserted.error);
+        let inserted = insert(&mut spell, 5, "f");
+        assert!(!inserted.success, "insert must fail out of bounds");
+
+        let push = spell.list_push_string_cp(key.into(), "e".into(), spell_call_params());
+        assert!(push.success, "push failed {}", push.error);
+
+        let get = spell.list_get_strings(key.into());
+        assert_eq!(get.value, vec!["a", "b", "c", "d", "e"]);
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_list_range(spell: marine_test_env::spell::ModuleInterface) {
+        let key = "a";
+        for value in ["a", "b", "c", "d"] {
+            let _ = spell.list_push_string_cp(key.into(), value.into(), spell_call_params());
+        }
+
+        let range = spell.list_range(key.into(), 1, 3);
+        assert!(range.success, "list_range failed {}", range.error);
+        assert_eq!(range.value, vec!["b", "c"]);
+
+        let range = spell.list_range(key.into(), 2, 10);
+        assert_eq!(range.value, vec!["c", "d"], "end must be clamped");
+
+        let range = spell.list_range(key.into(), 3, 1);
+        assert!(range.success, "list_range failed {}", range.error);
+        assert!(range.value.is_empty(), "reversed range must be empty");
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_list_shift_trim(spell: marine_test_env::spell::ModuleInterface) {
+        let key = "a";
+        for value in ["a", "b", "c", "d", "e"] {
+            let _ = spell.list_push_string_cp(key.into(), value.into(), spell_call_params());
+        }
+
+        let shift = spell.list_shift_cp(key.into(), spell_call_params());
+        assert!(shift.success, "list_shift failed {}", shift.error);
+        assert_eq!(shift.value, "a");
+
+        let trim = spell.list_trim_cp(key.into(), 2, spell_call_params());
+        assert!(trim.success, "list_trim failed {}", trim.error);
+        let get = spell.list_get_strings(key.into());
+        assert_eq!(
+            get.value,
+            vec!["d", "e"],
+            "trim must keep the newest elements"
+        );
+
+        let trim = spell.list_trim_cp(key.into(), 5, spell_call_params());
+        assert!(trim.success, "list_trim failed {}", trim.error);
+        let get = spell.list_get_strings(key.into());
+        assert_eq!(get.value, vec!["d", "e"], "short list must stay untouched");
+
+        let _ = spell.list_shift_cp(key.into(), spell_call_params());
+        let _ = spell.list_shift_cp(key.into(), spell_call_params());
+        let shift = spell.list_shift_cp(key.into(), spell_call_params());
+        assert!(shift.success, "list_shift failed {}", shift.error);
+        assert!(shift.absent, "shift of an empty list must be absent");
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_list_index_other(spell: marine_test_env::spell::ModuleInterface) {
+        let key = "a";
+        let _ = spell.list_push_string_cp(key.into(), "a".into(), spell_call_params());
+        let cp = || other_call_params();
+
+        let set = spell.list_set_at_cp(key.into(), 0, "b".into(), cp());
+        assert!(!set.success, "outside callers must not be able to write");
+        let insert = spell.list_insert_at_cp(key.into(), 0, "b".into(), cp());
+        assert!(!insert.success, "outside callers must not be able to write");
+        let shift = spell.list_shift_cp(key.into(), cp());
+        assert!(!shift.success, "outside callers must not be able to write");
+        let trim = spell.list_trim_cp(key.into(), 0, cp());
+        assert!(!trim.success, "outside callers must not be able to write");
+
+        let get = spell.list_get_strings(key.into());
+        assert_eq!(get.value, vec!["a"]);
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_u32_list(spell: marine_test_env::spell::ModuleInterface) {
+        let key = "blocks";
+        for value in [10, 20, 30, 20] {
+            let push = spell.list_push_u32_cp(key.into(), value, spell_call_params());
+            assert!(push.success, "list_push_u32 failed {}", push.error);
+        }
+
+        let get = spell.list_get_u32s(key.into());
+        assert!(get.success, "list_get_u32s failed {}", get.error);
+        assert_eq!(get.value, vec![10, 20, 30, 20]);
+
+        let pop = spell.list_pop_u32_cp(key.into(), spell_call_params());
+        assert!(pop.success, "list_pop_u32 failed {}", pop.error);
+        assert_eq!(pop.value, 20);
+
+        let remove = spell.list_remove_u32_cp(key.into(), 20, spell_call_params());
+        assert!(remove.success, "list_remove_u32 failed {}", remove.error);
+        let get = spell.list_get_u32s(key.into());
+        assert_eq!(get.value, vec![10, 30]);
+
+        let _ = spell.list_pop_u32_cp(key.into(), spell_call_params());
+        let _ = spell.list_pop_u32_cp(key.into(), spell_call_params());
+        let pop = spell.list_pop_u32_cp(key.into(), spell_call_params());
+        assert!(pop.success, "list_pop_u32 failed {}", pop.error);
+        assert!(pop.absent, "pop of an empty list must be absent");
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_list_types_not_mixed(spell: marine_test_env::spell::ModuleInterface) {
+        let _ = spell.list_push_u32_cp("nums".into(), 1, spell_call_params());
+        let push = spell.list_push_string_cp("nums".into(), "a".into(), spell_call_params());
+        assert!(!push.success, "strings must not be pushed to a list of u32");
+
+        let _ = spell.list_push_string_cp("strs".into(), "a".into(), spell_call_params());
+        let push = spell.list_push_u32_cp("strs".into(), 1, spell_call_params());
+        assert!(!push.success, "u32 must not be pushed to a list of strings");
+        let pop = spell.list_pop_u32_cp("strs".into(), spell_call_params());
+        assert!(
+            !pop.success,
+            "u32 must not be popped from a list of strings"
+        );
+        assert!(!spell.list_get_u32s("strs".into()).success);
+
+        // reading a list of u32 as strings fails instead of giving empty elements
+        let cp = || spell_call_params();
+        assert!(!spell.list_get_strings("nums".into()).success);
+        assert!(!spell.list_get_at("nums".into(), 0).success);
+        assert!(!spell.list_range("nums".into(), 0, 1).success);
+        assert!(!spell.list_len("nums".into()).success);
+        assert!(!spell.list_pop_string_cp("nums".into(), cp()).success);
+        assert!(!spell.list_shift_cp("nums".into(), cp()).success);
+        let set = spell.list_set_at_cp("nums".into(), 0, "a".into(), cp());
+        assert!(!set.success, "strings must not be set in a list of u32");
+        assert_eq!(spell.list_get_u32s("nums".into()).value, vec![1]);
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_set(spell: marine_test_env::spell::ModuleInterface) {
+        let key = "subscribers";
+        type SPELL = marine_test_env::spell::ModuleInterface;
+
+        let add = |spell: &mut SPELL, value: &str, expected: bool| {
+            let add = spell.set_add_cp(key.into(), value.into(), spell_call_params());
+            assert!(add.success, "set_add failed {}", add.error);
+            assert_eq!(add.value, expected, "set_add of {}", value);
+        };
+
+        add(&mut spell, "peer-a", true);
+        add(&mut spell, "peer-b", true);
+        add(&mut spell, "peer-a", false);
+        add(&mut spell, "", true);
+        add(&mut spell, "", false);
+
+        let members = spell.set_members(key.into());
+        assert!(members.success, "set_members failed {}", members.error);
+        assert_eq!(members.value, vec!["peer-a", "peer-b", ""]);
+
+        let contains = spell.set_contains(key.into(), "peer-b".into());
+        assert!(contains.success, "set_contains failed {}", contains.error);
+        assert!(contains.value, "peer-b must be in the set");
+
+        let remove = spell.set_remove_cp(key.into(), "peer-b".into(), spell_call_params());
+        assert!(remove.success, "set_remove failed {}", remove.error);
+        assert!(remove.value, "peer-b must be removed");
+        let remove = spell.set_remove_cp(key.into(), "peer-b".into(), spell_call_params());
+        assert!(remove.success, "set_remove failed {}", remove.error);
+        assert!(
+            !remove.value,
+            "absent value must not be reported as removed"
+        );
+
+        let contains = spell.set_contains(key.into(), "peer-b".into());
+        assert!(!contains.value, "peer-b must not be in the set");
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_u32_list_set_other(spell: marine_test_env::spell::ModuleInterface) {
+        let cp = || other_call_params();
+        let _ = spell.list_push_u32_cp("nums".into(), 1, spell_call_params());
+        let _ = spell.set_add_cp("set".into(), "a".into(), spell_call_params());
+
+        let push = spell.list_push_u32_cp("nums".into(), 2, cp());
+        assert!(!push.success, "outside callers must not be able to write");
+        let pop = spell.list_pop_u32_cp("nums".into(), cp());
+        assert!(!pop.success, "outside callers must not be able to write");
+        let remove = spell.list_remove_u32_cp("nums".into(), 1, cp());
+        assert!(!remove.success, "outside callers must not be able to write");
+        let add = spell.set_add_cp("set".into(), "b".into(), cp());
+        assert!(!add.success, "outside callers must not be able to write");
+        let remove = spell.set_remove_cp("set".into(), "a".into(), cp());
+        assert!(!remove.success, "outside callers must not be able to write");
+
+        assert_eq!(spell.list_get_u32s("nums".into()).value, vec![1]);
+        assert_eq!(spell.set_members("set".into()).value, vec!["a"]);
+    }
+
     #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
     fn test_list_host(spell: marine_test_env::spell::ModuleInterface) {
         let private_key = "key";
@@ -21,26 +21,233 @@ use std::collections::HashMap;
 
 use eyre::Context;
 use marine_rs_sdk::marine;
-use serde_json::Value as JValue;
+use marine_sqlite_connector::{Connection, Statement};
+use serde_json::{Map, Value as JValue};
 
 use crate::auth::guard_kv_write;
-use fluence_spell_dtos::value::UnitValue;
+use fluence_spell_dtos::value::{StringValue, UnitValue};
 
-use crate::kv::primitive::store_string;
+use crate::misc::{fetch_rows, in_transaction};
+use crate::quota::check_kv_usage;
+use crate::schema::db;
+
+/// Separates segments of the path keys that `set_json` flattens documents into,
+/// e.g. `cfg.peers.0.addr`.
+const PATH_SEPARATOR: char = '.';
+
+/// Stores JSON text under the `key` with the `json` value type, so `get_json` parses it back
+/// while plain strings stay strings. `get_string` returns the text as is.
+fn store_json(conn: &Connection, key: &str, json: &str) -> eyre::Result<()> {
+    let mut statement =
+        conn.prepare("INSERT OR REPLACE INTO kv (key, string, value_type) VALUES (?, ?, 'json')")?;
+    statement.bind(1, key)?;
+    statement.bind(2, json)?;
+    statement.next()?;
+
+    Ok(())
+}
 
 #[marine]
-/// Save all fields of the passed object to KV as strings.
+/// Save all fields of the passed object to KV as JSON text,
+/// readable with `get_string` and `get_json`.
+/// The object is stored atomically: if any field can't be written, none of them are.
 /// NOTE: this function is not recursive. It takes only first-level fields.
 pub fn set_json_fields(json: &str) -> UnitValue {
     let result: eyre::Result<()> = try {
         let fields: HashMap<String, JValue> =
             serde_json::from_str(json).context("passed string must represent a JSON object")?;
-        // TODO: should it be all in a single transaction?
-        for (key, value) in fields {
-            guard_kv_write(&key)?;
-            store_string(&key, value.to_string())
-                .context(format!("set string for field '{}' failed", key))?
+        for (key, value) in &fields {
+            guard_kv_write(key, Some(value.to_string().len()))?;
+        }
+
+        let conn = db();
+        in_transaction(&conn, |conn| {
+            for (key, value) in fields {
+                store_json(conn, &key, &value.to_string())
+                    .context(format!("set string for field '{}' failed", key))?
+            }
+            check_kv_usage(conn)
+        })?
+    };
+
+    result.into()
+}
+
+/// Flattens `value` into `(path, JSON text)` pairs.
+/// Every object and array is stored under its own path as an empty `{}` or `[]`,
+/// so the structure can be rebuilt even for empty containers.
+fn flatten(path: String, value: &JValue, entries: &mut Vec<(String, String)>) -> eyre::Result<()> {
+    match value {
+        JValue::Object(fields) => {
+            entries.push((path.clone(), "{}".to_string()));
+            for (field, value) in fields {
+                if field.is_empty() || field.contains(PATH_SEPARATOR) {
+                    Err(eyre::eyre!(
+                        "field '{field}' of '{path}' must be non-empty and must not contain '{PATH_SEPARATOR}'"
+                    ))?;
+                }
+                flatten(f!("{path}{PATH_SEPARATOR}{field}"), value, entries)?;
+            }
+        }
+        JValue::Array(items) => {
+            entries.push((path.clone(), "[]".to_string()));
+            for (idx, value) in items.iter().enumerate() {
+                flatten(f!("{path}{PATH_SEPARATOR}{idx}"), value, entries)?;
+            }
+        }
+        value => entries.push((path, value.to_string())),
+    }
+
+    Ok(())
+}
+
+/// Puts `value` into the `node` at the `path` relative to it, creating missing containers on the way.
+fn insert(node: &mut JValue, path: &[&str], value: JValue) -> eyre::Result<()> {
+    let (segment, rest) = match path.split_first() {
+        Some(split) => split,
+        None => {
+            *node = value;
+            return Ok(());
+        }
+    };
+
+    if node.is_null() {
+        *node = JValue::Object(Map::new());
+    }
+    match node {
+        JValue::Object(fields) => insert(
+            fields.entry(segment.to_string()).or_insert(JValue::Null),
+            rest,
+            value,
+        ),
+        JValue::Array(items) => {
+            let idx: usize = segment
+                .parse()
+                .context(format!("'{segment}' is not a valid array index"))?;
+            if items.len() <= idx {
+                items.resize(idx + 1, JValue::Null);
+            }
+            insert(&mut items[idx], rest, value)
+        }
+        _ => Err(eyre::eyre!(
+            "'{segment}' is nested into a non-container value"
+        )),
+    }
+}
+
+/// Converts a KV row into JSON.
+/// Values of the `json` type are parsed as JSON text, plain strings become JSON strings.
+/// Expects the `value_type, string, u32, i64, f64, bool, bytes` columns starting at `idx`.
+fn read_json_value(statement: &Statement, idx: usize) -> eyre::Result<JValue> {
+    let value = match statement.read::<String>(idx)?.as_str() {
+        "u32" => JValue::from(statement.read::<i64>(idx + 2)?),
+        "i64" => JValue::from(statement.read::<i64>(idx + 3)?),
+        "f64" => JValue::from(statement.read::<f64>(idx + 4)?),
+        "bool" => JValue::from(statement.read::<i64>(idx + 5)? != 0),
+        "bytes" => JValue::from(statement.read::<Vec<u8>>(idx + 6)?),
+        "json" => {
+            let value = statement.read::<String>(idx + 1)?;
+            serde_json::from_str(&value).context(format!("malformed JSON '{value}'"))?
+        }
+        _ => JValue::String(statement.read::<String>(idx + 1)?),
+    };
+
+    Ok(value)
+}
+
+/// Rebuilds the JSON document stored under `key` and its path keys.
+fn read_json(conn: &Connection, key: &str) -> eyre::Result<Option<JValue>> {
+    let prefix = f!("{key}{PATH_SEPARATOR}");
+    let mut statement = conn.prepare(
+        r#"
+        SELECT key, value_type, string, u32, i64, f64, bool, bytes
+          FROM kv
+         WHERE (key = ? OR substr(key, 1, length(?)) = ?)
+           AND list_order == -1
+           AND (expires_at IS NULL OR expires_at > strftime('%s', 'now'))
+        "#,
+    )?;
+    statement.bind(1, key)?;
+    statement.bind(2, prefix.as_str())?;
+    statement.bind(3, prefix.as_str())?;
+    let mut entries = fetch_rows(statement, |statement| {
+        let path = statement.read::<String>(0)?;
+        Ok(Some((path, read_json_value(statement, 1)?)))
+    });
+    if entries.is_empty() {
+        return Ok(None);
+    }
+
+    // containers must be in place before their items
+    entries.sort_by_key(|(path, _)| path.matches(PATH_SEPARATOR).count());
+    let mut document = JValue::Null;
+    for (path, value) in entries {
+        let segments: Vec<&str> = path[key.len()..].split(PATH_SEPARATOR).skip(1).collect();
+        insert(&mut document, &segments, value).context(format!("malformed JSON path '{path}'"))?;
+    }
+
+    Ok(Some(document))
+}
+
+#[marine]
+/// Store a JSON document under the `key`, replacing the previous one.
+/// Nested fields are flattened into path keys like `key.peers.0.addr`, each holding JSON text,
+/// so they can be read and updated separately, e.g. with `get_string` and `set_u32`.
+/// Object fields must not contain '.'.
+pub fn set_json(key: &str, json: &str) -> UnitValue {
+    let result: eyre::Result<()> = try {
+        let value: JValue = serde_json::from_str(json).context("passed string must be JSON")?;
+        let mut entries = vec![];
+        flatten(key.to_string(), &value, &mut entries)?;
+        for (path, value) in &entries {
+            guard_kv_write(path, Some(value.len()))?;
+        }
+
+        let conn = db();
+        in_transaction(&conn, |conn| {
+            let prefix = f!("{key}{PATH_SEPARATOR}");
+            let mut delete =
+                conn.prepare("DELETE FROM kv WHERE key = ? OR substr(key, 1, length(?)) = ?")?;
+            delete.bind(1, key)?;
+            delete.bind(2, prefix.as_str())?;
+            delete.bind(3, prefix.as_str())?;
+            delete.next()?;
+
+            for (path, value) in entries {
+                store_json(conn, &path, &value)
+                    .context(format!("set string for path '{}' failed", path))?
+            }
+            check_kv_usage(conn)
+        })?
+    };
+
+    result.into()
+}
+
+#[marine]
+/// Get the JSON document stored by `set_json` under the `key`.
+/// Also works for plain values, returning them as JSON.
+pub fn get_json(key: &str) -> StringValue {
+    let result: eyre::Result<Option<String>> =
+        try { read_json(&db(), key)?.map(|document| document.to_string()) };
+
+    result.into()
+}
+
+#[marine]
+/// Build a JSON object from several keys: `{"key": value, ...}`.
+/// Values are read the same way as in `get_json`. Absent keys are skipped.
+pub fn get_json_fields(keys: Vec<String>) -> StringValue {
+    let result: eyre::Result<Option<String>> = try {
+        let conn = db();
+        let mut fields = Map::new();
+        for key in keys {
+            if let Some(value) = read_json(&conn, &key)? {
+                fields.insert(key, value);
+            }
         }
+
+        Some(JValue::Object(fields).to_string())
     };
 
     result.into()
@@ -132,6 +339,114 @@ mod tests {
         assert!(!set.success, "set_json_fields must fail");
     }
 
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_set_get_json(spell: marine_test_env::spell::ModuleInterface) {
+        let json = json!({
+            "a": 1,
+            "b": {"foo": "bar", "empty": {}},
+            "c": [],
+            "peers": [{"addr": "/ip4/1"}, {"addr": "/ip4/2", "ports": [1, 2]}],
+            "d": null,
+            "e": true
+        });
+        let set = spell.set_json_cp("cfg".into(), json.to_string(), spell_call_params());
+        assert!(set.success, "set_json failed: {}", set.error);
+
+        let get = spell.get_json("cfg".into());
+        assert!(get.success, "get_json failed: {}", get.error);
+        assert!(!get.absent, "get_json must find the document");
+        let got: serde_json::Value = serde_json::from_str(&get.value).unwrap();
+        assert_eq!(got, json);
+
+        let addr = spell.get_string("cfg.peers.1.addr".into());
+        assert_eq!(addr.value, json!("/ip4/2").to_string());
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_json_types_round_trip(spell: marine_test_env::spell::ModuleInterface) {
+        let cp = || spell_call_params();
+        let json = json!({"s": "123", "t": "true", "n": 123, "b": true, "z": "null"});
+        let set = spell.set_json_cp("cfg".into(), json.to_string(), cp());
+        assert!(set.success, "set_json failed: {}", set.error);
+        let get = spell.get_json("cfg".into());
+        let got: serde_json::Value = serde_json::from_str(&get.value).unwrap();
+        assert_eq!(got, json);
+
+        // plain strings that look like JSON stay strings
+        let set = spell.set_string_cp("plain".into(), "123".into(), cp());
+        assert!(set.success, "set_string failed: {}", set.error);
+        let set = spell.set_json_fields_cp(json!({"field": "123"}).to_string(), cp());
+        assert!(set.success, "set_json_fields failed: {}", set.error);
+        let keys = vec!["plain".to_string(), "field".to_string()];
+        let get = spell.get_json_fields(keys);
+        let got: serde_json::Value = serde_json::from_str(&get.value).unwrap();
+        assert_eq!(got, json!({"plain": "123", "field": "123"}));
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_json_partial_update(spell: marine_test_env::spell::ModuleInterface) {
+        let cp = || spell_call_params();
+        let json = json!({"peers": [{"addr": "/ip4/1"}], "count": 1});
+        let set = spell.set_json_cp("cfg".into(), json.to_string(), cp());
+        assert!(set.success, "set_json failed: {}", set.error);
+
+        let set = spell.set_string_cp("cfg.peers.0.addr".into(), "/ip4/3".into(), cp());
+        assert!(set.success, "set_string failed: {}", set.error);
+        let set = spell.set_u32_cp("cfg.count".into(), 2, cp());
+        assert!(set.success, "set_u32 failed: {}", set.error);
+
+        let get = spell.get_json("cfg".into());
+        let got: serde_json::Value = serde_json::from_str(&get.value).unwrap();
+        assert_eq!(got, json!({"peers": [{"addr": "/ip4/3"}], "count": 2}));
+
+        // setting a document replaces the previous one completely
+        let set = spell.set_json_cp("cfg".into(), json!({"x": 1}).to_string(), cp());
+        assert!(set.success, "set_json failed: {}", set.error);
+        assert!(!spell.exists("cfg.peers.0.addr".into()).value);
+        let get = spell.get_json("cfg".into());
+        let got: serde_json::Value = serde_json::from_str(&get.value).unwrap();
+        assert_eq!(got, json!({"x": 1}));
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_set_json_invalid_field(spell: marine_test_env::spell::ModuleInterface) {
+        let json = json!({"a": {"b.c": 1}});
+        let set = spell.set_json_cp("cfg".into(), json.to_string(), spell_call_params());
+        assert!(!set.success, "set_json must reject fields with dots");
+        assert!(!spell.exists("cfg".into()).value, "nothing must be stored");
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_get_json_fields(spell: marine_test_env::spell::ModuleInterface) {
+        let cp = || spell_call_params();
+        let fields = json!({"a": 1, "b": {"foo": "bar"}});
+        let set = spell.set_json_fields_cp(fields.to_string(), cp());
+        assert!(set.success, "set_json_fields failed: {}", set.error);
+        let set = spell.set_json_cp("doc".into(), json!([1, {"x": "y"}]).to_string(), cp());
+        assert!(set.success, "set_json failed: {}", set.error);
+        let set = spell.set_u32_cp("num".into(), 5, cp());
+        assert!(set.success, "set_u32 failed: {}", set.error);
+
+        let keys = vec!["a", "b", "doc", "num", "absent"];
+        let get = spell.get_json_fields(keys.into_iter().map(String::from).collect());
+        assert!(get.success, "get_json_fields failed: {}", get.error);
+        let got: serde_json::Value = serde_json::from_str(&get.value).unwrap();
+        assert_eq!(
+            got,
+            json!({"a": 1, "b": {"foo": "bar"}, "doc": [1, {"x": "y"}], "num": 5})
+        );
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_set_json_other(spell: marine_test_env::spell::ModuleInterface) {
+        let json = json!({"a": 1});
+        let set = spell.set_json_cp("w_cfg".into(), json.to_string(), other_call_params());
+        assert!(!set.success, "set_json must fail");
+
+        let set = spell.set_json_cp("w_cfg".into(), json.to_string(), worker_call_params());
+        assert!(set.success, "set_json failed: {}", set.error);
+    }
+
     fn spell_call_params() -> CallParameters {
         CallParameters {
             particle: ParticleParameters {
@@ -0,0 +1,298 @@
+/*
+ * Aqua Spell Service
+ *
+ * Copyright (C) 2024 Fluence DAO
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation version 3 of the
+ * License.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use marine_rs_sdk::marine;
+
+use fluence_spell_dtos::value::{
+    BoolValue, MapEntry, MapValue, StringListValue, StringValue, UnitValue,
+};
+
+use crate::auth::guard_kv_write;
+use crate::kv::primitive::read_string;
+use crate::misc::fetch_rows;
+use crate::quota::{guard_kv_quota, KvSlot};
+use crate::schema::db;
+
+#[marine]
+/// Set a field of the map stored under `key`.
+/// Write permissions are derived from `key`, so `w_` and `hw_` maps can be shared as a whole.
+pub fn map_set(key: &str, field: &str, value: &str) -> UnitValue {
+    let result: eyre::Result<()> = try {
+        guard_kv_write(key, None)?;
+        let conn = db();
+        guard_kv_quota(&conn, key, KvSlot::Field(field), field.len() + value.len())?;
+        let mut statement =
+            conn.prepare("INSERT OR REPLACE INTO kv_map (key, field, value) VALUES (?, ?, ?)")?;
+        statement.bind(1, key)?;
+        statement.bind(2, field)?;
+        statement.bind(3, value)?;
+        statement.next()?;
+    };
+
+    result.into()
+}
+
+#[marine]
+pub fn map_get(key: &str, field: &str) -> StringValue {
+    let result: eyre::Result<Option<String>> = try {
+        let conn = db();
+        let mut statement = conn.prepare("SELECT value FROM kv_map WHERE key = ? AND field = ?")?;
+        statement.bind(1, key)?;
+        statement.bind(2, field)?;
+        read_string(&mut statement, 0)?
+    };
+
+    result.into()
+}
+
+#[marine]
+/// Remove a field from the map. Returns `true` if the field was there.
+pub fn map_remove(key: &str, field: &str) -> BoolValue {
+    let result: eyre::Result<bool> = try {
+        guard_kv_write(key, None)?;
+        let conn = db();
+        let mut statement = conn.prepare("DELETE FROM kv_map WHERE key = ? AND field = ?")?;
+        statement.bind(1, key)?;
+        statement.bind(2, field)?;
+        statement.next()?;
+
+        conn.changes() > 0
+    };
+
+    result.into()
+}
+
+#[marine]
+/// Get field names of the map sorted by name
+pub fn map_fields(key: &str) -> StringListValue {
+    let result: eyre::Result<Vec<String>> = try {
+        let conn = db();
+        let mut statement =
+            conn.prepare("SELECT field FROM kv_map WHERE key = ? ORDER BY field")?;
+        statement.bind(1, key)?;
+        fetch_rows(statement, |statement| {
+            Ok(Some(statement.read::<String>(0)?))
+        })
+    };
+
+    result.into()
+}
+
+#[marine]
+/// Get all fields of the map with their values sorted by field name
+pub fn map_get_all(key: &str) -> MapValue {
+    let result: eyre::Result<Vec<MapEntry>> = try {
+        let conn = db();
+        let mut statement =
+            conn.prepare("SELECT field, value FROM kv_map WHERE key = ? ORDER BY field")?;
+        statement.bind(1, key)?;
+        fetch_rows(statement, |statement| {
+            Ok(Some(MapEntry {
+                field: statement.read::<String>(0)?,
+                value: statement.read::<String>(1)?,
+            }))
+        })
+    };
+
+    result.into()
+}
+
+#[test_env_helpers::after_each]
+#[cfg(test)]
+mod tests {
+    use marine_rs_sdk::CallParameters;
+    use marine_rs_sdk::ParticleParameters;
+    use marine_rs_sdk_test::marine_test;
+
+    const DB_FILE: &str = "./tests_artifacts/spell.sqlite";
+
+    #[ctor::ctor]
+    /// usage of 'ctor' makes this function run only once
+    fn before_all_tests() {
+        std::fs::remove_file(DB_FILE).ok();
+    }
+
+    /// after_each macro copy-pastes this function into every test
+    fn after_each() {
+        std::fs::remove_file(DB_FILE).ok();
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_map(spell: marine_test_env::spell::ModuleInterface) {
+        let key = "peer_1";
+        let cp = || spell_call_params();
+
+        let set = spell.map_set_cp(key.into(), "status".into(), "online".into(), cp());
+        assert!(set.success, "map_set failed: {}", set.error);
+        let set = spell.map_set_cp(key.into(), "ts".into(), "100".into(), cp());
+        assert!(set.success, "map_set failed: {}", set.error);
+        let set = spell.map_set_cp(key.into(), "status".into(), "offline".into(), cp());
+        assert!(set.success, "map_set failed: {}", set.error);
+
+        let get = spell.map_get(key.into(), "status".into());
+        assert!(!get.absent, "map_get must return the field");
+        assert_eq!(get.value, "offline", "map_get failed: {}", get.error);
+        let get = spell.map_get(key.into(), "other".into());
+        assert!(get.success, "map_get failed: {}", get.error);
+        assert!(get.absent, "unknown field must be absent");
+
+        let fields = spell.map_fields(key.into());
+        assert!(fields.success, "map_fields failed: {}", fields.error);
+        assert_eq!(fields.value, vec!["status", "ts"]);
+
+        let all = spell.map_get_all(key.into());
+        assert!(all.success, "map_get_all failed: {}", all.error);
+        let all = all
+            .entries
+            .into_iter()
+            .map(|entry| (entry.field, entry.value))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            all,
+            vec![
+                ("status".to_string(), "offline".to_string()),
+                ("ts".to_string(), "100".to_string())
+            ]
+        );
+
+        let removed = spell.map_remove_cp(key.into(), "ts".into(), cp());
+        assert!(removed.success, "map_remove failed: {}", removed.error);
+        assert!(removed.value, "existing field must be removed");
+        let removed = spell.map_remove_cp(key.into(), "ts".into(), cp());
+        assert!(removed.success, "map_remove failed: {}", removed.error);
+        assert!(
+            !removed.value,
+            "absent field must not be reported as removed"
+        );
+        assert_eq!(spell.map_fields(key.into()).value, vec!["status"]);
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_map_remove_key(spell: marine_test_env::spell::ModuleInterface) {
+        let key = "peer_1";
+        let _ = spell.map_set_cp(
+            key.into(),
+            "status".into(),
+            "online".into(),
+            spell_call_params(),
+        );
+
+        let exists = spell.exists(key.into());
+        assert!(exists.value, "map must exist: {}", exists.error);
+
+        let removed = spell.remove_key_cp(key.into(), spell_call_params());
+        assert!(removed.success, "remove_key failed: {}", removed.error);
+
+        let exists = spell.exists(key.into());
+        assert!(!exists.value, "map must be removed with the key");
+        let all = spell.map_get_all(key.into());
+        assert!(all.entries.is_empty(), "map must be removed with the key");
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_map_permissions(spell: marine_test_env::spell::ModuleInterface) {
+        let cp = || worker_call_params();
+
+        let set = spell.map_set_cp("w_peers".into(), "status".into(), "online".into(), cp());
+        assert!(
+            set.success,
+            "worker must be able to write w_ maps: {}",
+            set.error
+        );
+        let set = spell.map_set_cp("hw_peers".into(), "status".into(), "online".into(), cp());
+        assert!(
+            set.success,
+            "worker must be able to write hw_ maps: {}",
+            set.error
+        );
+        let set = spell.map_set_cp("peers".into(), "status".into(), "online".into(), cp());
+        assert!(
+            !set.success,
+            "worker must not be able to write private maps"
+        );
+        let set = spell.map_set_cp("h_peers".into(), "status".into(), "online".into(), cp());
+        assert!(!set.success, "worker must not be able to write h_ maps");
+
+        let removed = spell.map_remove_cp("w_peers".into(), "status".into(), cp());
+        assert!(
+            removed.success,
+            "worker must be able to remove from w_ maps: {}",
+            removed.error
+        );
+
+        let set = spell.map_set_cp(
+            "w_peers".into(),
+            "status".into(),
+            "online".into(),
+            other_call_params(),
+        );
+        assert!(!set.success, "outside callers must not be able to write");
+        let removed = spell.map_remove_cp("hw_peers".into(), "status".into(), other_call_params());
+        assert!(
+            !removed.success,
+            "outside callers must not be able to write"
+        );
+        assert_eq!(spell.map_fields("hw_peers".into()).value, vec!["status"]);
+    }
+
+    fn spell_call_params() -> CallParameters {
+        CallParameters {
+            particle: ParticleParameters {
+                init_peer_id: "worker-id".to_string(),
+                id: "spell_spell-id_0".to_string(),
+                ..<_>::default()
+            },
+            service_creator_peer_id: "worker-id".to_string(),
+            service_id: "spell-id".to_string(),
+            worker_id: "worker-id".to_string(),
+            host_id: "host-id".to_string(),
+            tetraplets: vec![],
+        }
+    }
+
+    fn worker_call_params() -> CallParameters {
+        CallParameters {
+            particle: ParticleParameters {
+                init_peer_id: "worker-id".to_string(),
+                id: "some-particle".to_string(),
+                ..<_>::default()
+            },
+            service_creator_peer_id: "worker-id".to_string(),
+            service_id: "spell-id".to_string(),
+            worker_id: "worker-id".to_string(),
+            host_id: "host-id".to_string(),
+            tetraplets: vec![],
+        }
+    }
+
+    fn other_call_params() -> CallParameters {
+        CallParameters {
+            particle: ParticleParameters {
+                init_peer_id: "other-worker-id".to_string(),
+                id: "some-particle".to_string(),
+                ..<_>::default()
+            },
+            service_creator_peer_id: "worker-id".to_string(),
+            service_id: "spell-id".to_string(),
+            worker_id: "worker-id".to_string(),
+            host_id: "host-id".to_string(),
+            tetraplets: vec![],
+        }
+    }
+}
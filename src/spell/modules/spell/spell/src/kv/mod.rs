@@ -17,6 +17,9 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+pub mod batch;
 pub mod collection;
 pub mod json;
+pub mod map;
 pub mod primitive;
+pub mod scan;
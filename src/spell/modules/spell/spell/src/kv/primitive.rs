@@ -18,23 +18,24 @@
  */
 
 use marine_rs_sdk::marine;
-use marine_sqlite_connector::{State, Statement};
+use marine_sqlite_connector::{Connection, State, Statement};
 
 use crate::auth::guard_kv_write;
-use fluence_spell_dtos::value::{BoolValue, StringValue, U32Value, UnitValue};
+use fluence_spell_dtos::value::{
+    BoolValue, BytesValue, F64Value, I64Value, OptBoolValue, StringValue, U32Value, UnitValue,
+};
 
+use crate::misc::in_transaction;
 use crate::schema::db;
 
-//
-// Note that it's possible to call this function on an empty string value, but it will be stored as a NULL value
-// in the database since SQLite connector we use don't save an empty string as an empty string which IS possible
-// if you manually try to do so. I didn't found WHY it's happening, but it's not a really big deal, just annoying.
-//
-pub fn store_string(key: &str, value: String) -> eyre::Result<()> {
-    let conn = db();
-    let mut statement = conn.prepare("INSERT OR REPLACE INTO kv (key, string) VALUES (?, ?)")?;
+/// Every value is stored with its type in the `value_type` column.
+/// The SQLite connector passes an empty string as NULL, so it's coalesced back to ''.
+pub fn store_string(conn: &Connection, key: &str, value: &str) -> eyre::Result<()> {
+    let mut statement = conn.prepare(
+        "INSERT OR REPLACE INTO kv (key, string, value_type) VALUES (?, COALESCE(?, ''), 'string')",
+    )?;
     statement.bind(1, key)?;
-    statement.bind(2, value.as_str())?;
+    statement.bind(2, value)?;
     statement.next()?;
 
     Ok(())
@@ -43,8 +44,29 @@ pub fn store_string(key: &str, value: String) -> eyre::Result<()> {
 #[marine]
 pub fn set_string(key: &str, value: String) -> UnitValue {
     let result: eyre::Result<()> = try {
-        guard_kv_write(key)?;
-        store_string(key, value)?
+        guard_kv_write(key, Some(value.len()))?;
+        store_string(&db(), key, &value)?
+    };
+    result.into()
+}
+
+#[marine]
+/// Sets a string value that reads as absent after `ttl_sec` seconds.
+/// Overwriting the key with `set_string` or `set_u32` removes the expiry.
+pub fn set_string_with_ttl(key: &str, value: String, ttl_sec: u32) -> UnitValue {
+    let result: eyre::Result<()> = try {
+        guard_kv_write(key, Some(value.len()))?;
+        let conn = db();
+        let mut statement = conn.prepare(
+            r#"
+            INSERT OR REPLACE INTO kv (key, string, value_type, expires_at)
+            VALUES (?, COALESCE(?, ''), 'string', strftime('%s', 'now') + ?)
+            "#,
+        )?;
+        statement.bind(1, key)?;
+        statement.bind(2, value.as_str())?;
+        statement.bind(3, ttl_sec as i64)?;
+        statement.next()?;
     };
     result.into()
 }
@@ -60,19 +82,20 @@ pub fn read_string(statement: &mut Statement, idx: usize) -> eyre::Result<Option
 }
 
 #[marine]
+/// Get the string value of the key.
+/// JSON stored by `set_json` and `set_json_fields` is returned as text.
 pub fn get_string(key: &str) -> StringValue {
     let result: eyre::Result<Option<String>> = try {
         let conn = db();
-        // As long as an empty string is saved a NULL value, we can determine that the value is a string
-        // by checking that the other possible type, u32, is null.
         // list_order == -1 when the value isn't part of the list
         let mut statement = conn.prepare(
             r#"
             SELECT string
               FROM kv
              WHERE key = ?
-               AND u32 IS NULL
+               AND value_type IN ('string', 'json')
                AND list_order == -1
+               AND (expires_at IS NULL OR expires_at > strftime('%s', 'now'))
             "#,
         )?;
         statement.bind(1, key)?;
@@ -81,14 +104,42 @@ pub fn get_string(key: &str) -> StringValue {
     result.into()
 }
 
+pub fn store_u32(conn: &Connection, key: &str, value: u32) -> eyre::Result<()> {
+    let mut statement =
+        conn.prepare("INSERT OR REPLACE INTO kv (key, u32, value_type) VALUES (?, ?, 'u32')")?;
+    statement.bind(1, key)?;
+    statement.bind(2, value as i64)?;
+    statement.next()?;
+
+    Ok(())
+}
+
 #[marine]
 pub fn set_u32(key: &str, value: u32) -> UnitValue {
     let result: eyre::Result<()> = try {
-        guard_kv_write(key)?;
+        guard_kv_write(key, Some(value.to_string().len()))?;
+        store_u32(&db(), key, value)?
+    };
+
+    result.into()
+}
+
+#[marine]
+/// Sets a u32 value that reads as absent after `ttl_sec` seconds.
+/// Overwriting the key with `set_u32` or `set_string` removes the expiry.
+pub fn set_u32_with_ttl(key: &str, value: u32, ttl_sec: u32) -> UnitValue {
+    let result: eyre::Result<()> = try {
+        guard_kv_write(key, Some(value.to_string().len()))?;
         let conn = db();
-        let mut statement = conn.prepare("INSERT OR REPLACE INTO kv (key, u32) VALUES (?, ?)")?;
+        let mut statement = conn.prepare(
+            r#"
+            INSERT OR REPLACE INTO kv (key, u32, value_type, expires_at)
+            VALUES (?, ?, 'u32', strftime('%s', 'now') + ?)
+            "#,
+        )?;
         statement.bind(1, key)?;
         statement.bind(2, value as i64)?;
+        statement.bind(3, ttl_sec as i64)?;
         statement.next()?;
     };
 
@@ -109,14 +160,14 @@ pub fn get_u32(key: &str) -> U32Value {
     let result: eyre::Result<Option<u32>> = try {
         let conn = db();
         // list_order == -1 when the value isn't part of the list
-        // when u32 is NULL it means that the value is a string or a list
         let mut statement = conn.prepare(
             r#"
             SELECT u32
               FROM kv
              WHERE key = ?
-               AND u32 IS NOT NULL
+               AND value_type = 'u32'
                AND list_order == -1
+               AND (expires_at IS NULL OR expires_at > strftime('%s', 'now'))
             "#,
         )?;
         statement.bind(1, key)?;
@@ -126,27 +177,320 @@ pub fn get_u32(key: &str) -> U32Value {
     result.into()
 }
 
+/// Reads, updates and writes back an integer value stored in the `column` in a single transaction,
+/// so concurrent read-modify-write cycles from different particles can't lose updates.
+/// `update` receives the current value (`None` if the key is absent) and returns the value to store.
+/// Fails if the key holds a value of another type.
+fn update_integer<F>(key: &str, column: &str, update: F) -> eyre::Result<Option<i64>>
+where
+    F: FnOnce(Option<i64>) -> eyre::Result<Option<i64>>,
+{
+    // the new value isn't known yet, so the room for the longest one is checked
+    guard_kv_write(key, Some(i64::MIN.to_string().len()))?;
+    let conn = db();
+    in_transaction(&conn, |conn| {
+        let mut get = conn.prepare(f!(
+            "SELECT {column}, value_type FROM kv WHERE key = ? AND list_order == -1 \
+             AND (expires_at IS NULL OR expires_at > strftime('%s', 'now'))"
+        ))?;
+        get.bind(1, key)?;
+        let current = if let State::Row = get.next()? {
+            let value_type = get.read::<String>(1)?;
+            if value_type != column {
+                Err(eyre::eyre!(
+                    "the `{key}` holds a value of type {value_type}, not {column}"
+                ))?;
+            }
+            Some(get.read::<i64>(0)?)
+        } else {
+            None
+        };
+
+        let new = update(current)?;
+        if let Some(value) = new.filter(|value| Some(*value) != current) {
+            let mut set = conn.prepare(f!(
+                "INSERT OR REPLACE INTO kv (key, {column}, value_type) VALUES (?, ?, '{column}')"
+            ))?;
+            set.bind(1, key)?;
+            set.bind(2, value)?;
+            set.next()?;
+        }
+
+        Ok(new)
+    })
+}
+
 #[marine]
-/// Deletes a key (and associated value/lists) from K/V.
-/// Always succeeds.
-pub fn remove_key(key: &str) -> UnitValue {
+/// Atomically adds `delta` to the u32 value of the key and returns the result.
+/// An absent key is considered to be 0. Fails on overflow.
+pub fn increment_u32(key: &str, delta: u32) -> U32Value {
+    let result = update_integer(key, "u32", |current| {
+        let current = current.unwrap_or_default() as u32;
+        let value = current
+            .checked_add(delta)
+            .ok_or_else(|| eyre::eyre!("incrementing `{key}` by {delta} overflows u32"))?;
+        Ok(Some(value as i64))
+    });
+
+    result.map(|value| value.map(|value| value as u32)).into()
+}
+
+#[marine]
+/// Atomically subtracts `delta` from the u32 value of the key and returns the result.
+/// An absent key is considered to be 0. Fails on underflow.
+pub fn decrement_u32(key: &str, delta: u32) -> U32Value {
+    let result = update_integer(key, "u32", |current| {
+        let current = current.unwrap_or_default() as u32;
+        let value = current
+            .checked_sub(delta)
+            .ok_or_else(|| eyre::eyre!("decrementing `{key}` by {delta} underflows u32"))?;
+        Ok(Some(value as i64))
+    });
+
+    result.map(|value| value.map(|value| value as u32)).into()
+}
+
+#[marine]
+/// Atomically sets the u32 value of the key to `new` if it's currently equal to `expected`.
+/// Returns the value stored after the operation, so the swap happened if it's equal to `new`.
+/// If the key is absent, nothing is stored and `absent` is true.
+pub fn compare_and_swap_u32(key: &str, expected: u32, new: u32) -> U32Value {
+    let result = update_integer(key, "u32", |current| {
+        if current == Some(expected as i64) {
+            Ok(Some(new as i64))
+        } else {
+            Ok(current)
+        }
+    });
+
+    result.map(|value| value.map(|value| value as u32)).into()
+}
+
+#[marine]
+pub fn set_i64(key: &str, value: i64) -> UnitValue {
     let result: eyre::Result<()> = try {
-        guard_kv_write(key)?;
+        guard_kv_write(key, Some(value.to_string().len()))?;
         let conn = db();
-        let mut statement = conn.prepare("DELETE FROM kv WHERE key = ?")?;
+        let mut statement =
+            conn.prepare("INSERT OR REPLACE INTO kv (key, i64, value_type) VALUES (?, ?, 'i64')")?;
         statement.bind(1, key)?;
+        statement.bind(2, value)?;
         statement.next()?;
     };
 
     result.into()
 }
 
+#[marine]
+pub fn get_i64(key: &str) -> I64Value {
+    let result: eyre::Result<Option<i64>> = try {
+        let conn = db();
+        let mut statement = conn.prepare(
+            r#"
+            SELECT i64
+              FROM kv
+             WHERE key = ?
+               AND value_type = 'i64'
+               AND list_order == -1
+               AND (expires_at IS NULL OR expires_at > strftime('%s', 'now'))
+            "#,
+        )?;
+        statement.bind(1, key)?;
+        if let State::Row = statement.next()? {
+            Some(statement.read::<i64>(0)?)
+        } else {
+            None
+        }
+    };
+
+    result.into()
+}
+
+#[marine]
+/// Atomically adds `delta` to the i64 value of the key and returns the result.
+/// An absent key is considered to be 0. Use a negative `delta` to decrement. Fails on overflow.
+pub fn increment_i64(key: &str, delta: i64) -> I64Value {
+    let result = update_integer(key, "i64", |current| {
+        let value = current
+            .unwrap_or_default()
+            .checked_add(delta)
+            .ok_or_else(|| eyre::eyre!("incrementing `{key}` by {delta} overflows i64"))?;
+        Ok(Some(value))
+    });
+
+    result.into()
+}
+
+#[marine]
+/// Note that SQLite stores NaN as NULL, so NaN values are rejected.
+pub fn set_f64(key: &str, value: f64) -> UnitValue {
+    let result: eyre::Result<()> = try {
+        guard_kv_write(key, Some(value.to_string().len()))?;
+        if value.is_nan() {
+            Err(eyre::eyre!("NaN can't be stored in the `{key}`"))?;
+        }
+        let conn = db();
+        let mut statement =
+            conn.prepare("INSERT OR REPLACE INTO kv (key, f64, value_type) VALUES (?, ?, 'f64')")?;
+        statement.bind(1, key)?;
+        statement.bind(2, value)?;
+        statement.next()?;
+    };
+
+    result.into()
+}
+
+#[marine]
+pub fn get_f64(key: &str) -> F64Value {
+    let result: eyre::Result<Option<f64>> = try {
+        let conn = db();
+        let mut statement = conn.prepare(
+            r#"
+            SELECT f64
+              FROM kv
+             WHERE key = ?
+               AND value_type = 'f64'
+               AND list_order == -1
+               AND (expires_at IS NULL OR expires_at > strftime('%s', 'now'))
+            "#,
+        )?;
+        statement.bind(1, key)?;
+        if let State::Row = statement.next()? {
+            Some(statement.read::<f64>(0)?)
+        } else {
+            None
+        }
+    };
+
+    result.into()
+}
+
+#[marine]
+pub fn set_bool(key: &str, value: bool) -> UnitValue {
+    let result: eyre::Result<()> = try {
+        // stored as 0 or 1
+        guard_kv_write(key, Some(1))?;
+        let conn = db();
+        let mut statement = conn
+            .prepare("INSERT OR REPLACE INTO kv (key, bool, value_type) VALUES (?, ?, 'bool')")?;
+        statement.bind(1, key)?;
+        statement.bind(2, value as i64)?;
+        statement.next()?;
+    };
+
+    result.into()
+}
+
+#[marine]
+pub fn get_bool(key: &str) -> OptBoolValue {
+    let result: eyre::Result<Option<bool>> = try {
+        let conn = db();
+        let mut statement = conn.prepare(
+            r#"
+            SELECT bool
+              FROM kv
+             WHERE key = ?
+               AND value_type = 'bool'
+               AND list_order == -1
+               AND (expires_at IS NULL OR expires_at > strftime('%s', 'now'))
+            "#,
+        )?;
+        statement.bind(1, key)?;
+        if let State::Row = statement.next()? {
+            Some(statement.read::<i64>(0)? != 0)
+        } else {
+            None
+        }
+    };
+
+    result.into()
+}
+
+#[marine]
+pub fn set_bytes(key: &str, value: Vec<u8>) -> UnitValue {
+    let result: eyre::Result<()> = try {
+        guard_kv_write(key, Some(value.len()))?;
+        let conn = db();
+        // An empty blob reaches SQLite as NULL, the same way an empty string does (see `store_string`)
+        let mut statement = conn.prepare(
+            "INSERT OR REPLACE INTO kv (key, bytes, value_type) VALUES (?, COALESCE(?, X''), 'bytes')",
+        )?;
+        statement.bind(1, key)?;
+        statement.bind(2, &value)?;
+        statement.next()?;
+    };
+
+    result.into()
+}
+
+#[marine]
+pub fn get_bytes(key: &str) -> BytesValue {
+    let result: eyre::Result<Option<Vec<u8>>> = try {
+        let conn = db();
+        let mut statement = conn.prepare(
+            r#"
+            SELECT bytes
+              FROM kv
+             WHERE key = ?
+               AND value_type = 'bytes'
+               AND list_order == -1
+               AND (expires_at IS NULL OR expires_at > strftime('%s', 'now'))
+            "#,
+        )?;
+        statement.bind(1, key)?;
+        if let State::Row = statement.next()? {
+            Some(statement.read::<Vec<u8>>(0)?)
+        } else {
+            None
+        }
+    };
+
+    result.into()
+}
+
+pub fn delete_key(conn: &Connection, key: &str) -> eyre::Result<()> {
+    let mut statement = conn.prepare("DELETE FROM kv WHERE key = ?")?;
+    statement.bind(1, key)?;
+    statement.next()?;
+
+    let mut statement = conn.prepare("DELETE FROM kv_map WHERE key = ?")?;
+    statement.bind(1, key)?;
+    statement.next()?;
+
+    Ok(())
+}
+
+#[marine]
+/// Deletes a key (and associated value/lists/map fields) from K/V.
+/// Always succeeds.
+pub fn remove_key(key: &str) -> UnitValue {
+    let result: eyre::Result<()> = try {
+        guard_kv_write(key, None)?;
+        delete_key(&db(), key)?
+    };
+
+    result.into()
+}
+
 #[marine]
 pub fn exists(key: &str) -> BoolValue {
     let result: eyre::Result<bool> = try {
         let conn = db();
-        let mut statement = conn.prepare("SELECT 1 FROM kv WHERE key = ? LIMIT 1")?;
+        let mut statement = conn.prepare(
+            r#"
+            SELECT 1
+              FROM kv
+             WHERE key = ?
+               AND (expires_at IS NULL OR expires_at > strftime('%s', 'now'))
+         UNION ALL
+            SELECT 1
+              FROM kv_map
+             WHERE key = ?
+             LIMIT 1
+            "#,
+        )?;
         statement.bind(1, key)?;
+        statement.bind(2, key)?;
 
         match statement.next()? {
             State::Row => true,
@@ -199,6 +543,274 @@ mod tests {
         assert_eq!(get.value, num, "get_u32 failed: {}", get.error);
     }
 
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_i64(spell: marine_test_env::spell::ModuleInterface) {
+        let key = "num".to_string();
+        let num = -(u32::MAX as i64) * 3;
+        let set = spell.set_i64_cp(key.clone(), num, spell_call_params());
+        assert!(set.success, "set_i64 failed: {}", set.error);
+        let get = spell.get_i64(key);
+        assert!(!get.absent, "get_i64 must return the stored value");
+        assert_eq!(get.value, num, "get_i64 failed: {}", get.error);
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_f64(spell: marine_test_env::spell::ModuleInterface) {
+        let key = "price".to_string();
+        let num = -12.375;
+        let set = spell.set_f64_cp(key.clone(), num, spell_call_params());
+        assert!(set.success, "set_f64 failed: {}", set.error);
+        let get = spell.get_f64(key.clone());
+        assert!(!get.absent, "get_f64 must return the stored value");
+        assert_eq!(get.value, num, "get_f64 failed: {}", get.error);
+
+        let set = spell.set_f64_cp(key, f64::NAN, spell_call_params());
+        assert!(!set.success, "set_f64 must reject NaN");
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_bool(spell: marine_test_env::spell::ModuleInterface) {
+        let key = "flag".to_string();
+        for value in [true, false] {
+            let set = spell.set_bool_cp(key.clone(), value, spell_call_params());
+            assert!(set.success, "set_bool failed: {}", set.error);
+            let get = spell.get_bool(key.clone());
+            assert!(!get.absent, "get_bool must return the stored value");
+            assert_eq!(get.value, value, "get_bool failed: {}", get.error);
+        }
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_bytes(spell: marine_test_env::spell::ModuleInterface) {
+        let key = "blob".to_string();
+        for value in [vec![0, 1, 2, 255], vec![]] {
+            let set = spell.set_bytes_cp(key.clone(), value.clone(), spell_call_params());
+            assert!(set.success, "set_bytes failed: {}", set.error);
+            let get = spell.get_bytes(key.clone());
+            assert!(!get.absent, "get_bytes must return the stored value");
+            assert_eq!(get.value, value, "get_bytes failed: {}", get.error);
+
+            let get_str = spell.get_string(key.clone());
+            assert!(get_str.absent, "bytes must not be read as a string");
+        }
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_typed_values_absent(spell: marine_test_env::spell::ModuleInterface) {
+        let key = "num".to_string();
+
+        let get = spell.get_i64(key.clone());
+        assert!(get.success, "get_i64 failed: {}", get.error);
+        assert!(get.absent, "key {} exists", key);
+
+        let set = spell.set_i64_cp(key.clone(), 5, spell_call_params());
+        assert!(set.success, "set_i64 failed: {}", set.error);
+
+        let get_u32 = spell.get_u32(key.clone());
+        assert!(get_u32.absent, "the value of the wrong type must be absent");
+        let get_f64 = spell.get_f64(key.clone());
+        assert!(get_f64.absent, "the value of the wrong type must be absent");
+        let get_bool = spell.get_bool(key.clone());
+        assert!(
+            get_bool.absent,
+            "the value of the wrong type must be absent"
+        );
+        let get_bytes = spell.get_bytes(key.clone());
+        assert!(
+            get_bytes.absent,
+            "the value of the wrong type must be absent"
+        );
+        let get_str = spell.get_string(key);
+        assert!(get_str.absent, "the value of the wrong type must be absent");
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_typed_values_other(spell: marine_test_env::spell::ModuleInterface) {
+        let key = "w_num".to_string();
+        let cp = || other_call_params();
+
+        assert!(!spell.set_i64_cp(key.clone(), 1, cp()).success);
+        assert!(!spell.set_f64_cp(key.clone(), 1.0, cp()).success);
+        assert!(!spell.set_bool_cp(key.clone(), true, cp()).success);
+        assert!(!spell.set_bytes_cp(key.clone(), vec![1], cp()).success);
+
+        let exists = spell.exists(key);
+        assert!(!exists.value, "outside callers must not be able to write");
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_increment_decrement_u32(spell: marine_test_env::spell::ModuleInterface) {
+        let key = "w_counter".to_string();
+        let cp = || worker_call_params();
+
+        let inc = spell.increment_u32_cp(key.clone(), 5, cp());
+        assert!(inc.success, "increment_u32 failed: {}", inc.error);
+        assert_eq!(inc.value, 5, "absent key must be incremented from 0");
+
+        let inc = spell.increment_u32_cp(key.clone(), 3, cp());
+        assert!(inc.success, "increment_u32 failed: {}", inc.error);
+        assert_eq!(inc.value, 8);
+
+        let dec = spell.decrement_u32_cp(key.clone(), 8, cp());
+        assert!(dec.success, "decrement_u32 failed: {}", dec.error);
+        assert_eq!(dec.value, 0);
+
+        let dec = spell.decrement_u32_cp(key.clone(), 1, cp());
+        assert!(!dec.success, "decrement_u32 must fail on underflow");
+
+        let set = spell.set_u32_cp(key.clone(), u32::MAX, cp());
+        assert!(set.success, "set_u32 failed: {}", set.error);
+        let inc = spell.increment_u32_cp(key.clone(), 1, cp());
+        assert!(!inc.success, "increment_u32 must fail on overflow");
+
+        let get = spell.get_u32(key);
+        assert_eq!(
+            get.value,
+            u32::MAX,
+            "failed operations must not change the value"
+        );
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_increment_i64(spell: marine_test_env::spell::ModuleInterface) {
+        let key = "counter".to_string();
+        let cp = || spell_call_params();
+
+        let inc = spell.increment_i64_cp(key.clone(), -5, cp());
+        assert!(inc.success, "increment_i64 failed: {}", inc.error);
+        assert_eq!(inc.value, -5);
+
+        let inc = spell.increment_i64_cp(key.clone(), 7, cp());
+        assert!(inc.success, "increment_i64 failed: {}", inc.error);
+        assert_eq!(inc.value, 2);
+
+        let get = spell.get_i64(key);
+        assert_eq!(get.value, 2, "get_i64 failed: {}", get.error);
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_compare_and_swap_u32(spell: marine_test_env::spell::ModuleInterface) {
+        let key = "num".to_string();
+        let cp = || spell_call_params();
+
+        let cas = spell.compare_and_swap_u32_cp(key.clone(), 0, 1, cp());
+        assert!(cas.success, "compare_and_swap_u32 failed: {}", cas.error);
+        assert!(cas.absent, "absent key must not be swapped");
+
+        spell.set_u32_cp(key.clone(), 10, cp());
+
+        let cas = spell.compare_and_swap_u32_cp(key.clone(), 9, 20, cp());
+        assert!(cas.success, "compare_and_swap_u32 failed: {}", cas.error);
+        assert_eq!(cas.value, 10, "value must not be swapped");
+
+        let cas = spell.compare_and_swap_u32_cp(key.clone(), 10, 20, cp());
+        assert!(cas.success, "compare_and_swap_u32 failed: {}", cas.error);
+        assert_eq!(cas.value, 20, "value must be swapped");
+
+        let get = spell.get_u32(key);
+        assert_eq!(get.value, 20, "get_u32 failed: {}", get.error);
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_increment_wrong_type(spell: marine_test_env::spell::ModuleInterface) {
+        let key = "str".to_string();
+        let cp = || spell_call_params();
+
+        spell.set_string_cp(key.clone(), "value".into(), cp());
+
+        let inc = spell.increment_u32_cp(key.clone(), 1, cp());
+        assert!(!inc.success, "increment_u32 of a string must fail");
+
+        let get = spell.get_string(key);
+        assert_eq!(get.value, "value", "the string must stay intact");
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_counters_other(spell: marine_test_env::spell::ModuleInterface) {
+        let key = "w_counter".to_string();
+        let cp = || other_call_params();
+
+        assert!(!spell.increment_u32_cp(key.clone(), 1, cp()).success);
+        assert!(!spell.decrement_u32_cp(key.clone(), 1, cp()).success);
+        assert!(
+            !spell
+                .compare_and_swap_u32_cp(key.clone(), 0, 1, cp())
+                .success
+        );
+        assert!(!spell.increment_i64_cp(key.clone(), 1, cp()).success);
+
+        let exists = spell.exists(key);
+        assert!(!exists.value, "outside callers must not be able to write");
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_string_with_ttl(spell: marine_test_env::spell::ModuleInterface) {
+        let key = "relay".to_string();
+        let set = spell.set_string_with_ttl_cp(key.clone(), "addr".into(), 1, spell_call_params());
+        assert!(set.success, "set_string_with_ttl failed: {}", set.error);
+        let get = spell.get_string(key.clone());
+        assert!(!get.absent, "value must be present before expiry");
+        assert_eq!(get.value, "addr", "get_string failed: {}", get.error);
+
+        std::thread::sleep(std::time::Duration::from_secs(2));
+
+        let get = spell.get_string(key.clone());
+        assert!(get.success, "get_string failed: {}", get.error);
+        assert!(get.absent, "expired value must be absent");
+        let exists = spell.exists(key);
+        assert!(exists.success, "exists failed: {}", exists.error);
+        assert!(!exists.value, "expired value must not exist");
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_u32_with_ttl(spell: marine_test_env::spell::ModuleInterface) {
+        let key = "last_seen".to_string();
+        let set = spell.set_u32_with_ttl_cp(key.clone(), 42, 1, spell_call_params());
+        assert!(set.success, "set_u32_with_ttl failed: {}", set.error);
+        let get = spell.get_u32(key.clone());
+        assert!(!get.absent, "value must be present before expiry");
+        assert_eq!(get.value, 42, "get_u32 failed: {}", get.error);
+
+        std::thread::sleep(std::time::Duration::from_secs(2));
+
+        let get = spell.get_u32(key.clone());
+        assert!(get.success, "get_u32 failed: {}", get.error);
+        assert!(get.absent, "expired value must be absent");
+        let exists = spell.exists(key.clone());
+        assert!(!exists.value, "expired value must not exist");
+
+        // an expired counter starts over
+        let inc = spell.increment_u32_cp(key, 1, spell_call_params());
+        assert!(inc.success, "increment_u32 failed: {}", inc.error);
+        assert_eq!(inc.value, 1, "expired value must be considered absent");
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_ttl_overwritten(spell: marine_test_env::spell::ModuleInterface) {
+        let key = "relay".to_string();
+        let set = spell.set_string_with_ttl_cp(key.clone(), "addr".into(), 1, spell_call_params());
+        assert!(set.success, "set_string_with_ttl failed: {}", set.error);
+        let set = spell.set_string_cp(key.clone(), "other".into(), spell_call_params());
+        assert!(set.success, "set_string failed: {}", set.error);
+
+        std::thread::sleep(std::time::Duration::from_secs(2));
+
+        let get = spell.get_string(key);
+        assert!(!get.absent, "set_string must remove the expiry");
+        assert_eq!(get.value, "other", "get_string failed: {}", get.error);
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_ttl_other(spell: marine_test_env::spell::ModuleInterface) {
+        let key = "relay".to_string();
+        let set = spell.set_string_with_ttl_cp(key.clone(), "addr".into(), 60, other_call_params());
+        assert!(!set.success, "outside callers must not be able to write");
+        let set = spell.set_u32_with_ttl_cp(key.clone(), 1, 60, other_call_params());
+        assert!(!set.success, "outside callers must not be able to write");
+        let exists = spell.exists(key);
+        assert!(!exists.value, "outside callers must not be able to write");
+    }
+
     #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
     fn test_remove_key(spell: marine_test_env::spell::ModuleInterface) {
         let key = "num";
@@ -338,6 +950,28 @@ mod tests {
         assert_eq!(get.value, "", "get_string failed: {}", get.error);
     }
 
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_empty_string_typed(spell: marine_test_env::spell::ModuleInterface) {
+        let key = "str".to_string();
+        let set = spell.set_string_cp(key.clone(), String::new(), spell_call_params());
+        assert!(set.success, "set_string failed: {}", set.error);
+
+        let get = spell.get_u32(key.clone());
+        assert!(get.absent, "empty string must not be read as u32");
+        let get = spell.get_bytes(key.clone());
+        assert!(get.absent, "empty string must not be read as bytes");
+
+        let keys = spell.list_keys(key.clone(), 10, 0);
+        assert!(keys.success, "list_keys failed: {}", keys.error);
+        assert_eq!(keys.keys.len(), 1);
+        assert_eq!(keys.keys[0].value_type, "string");
+
+        let set = spell.set_u32_cp(key.clone(), 0, spell_call_params());
+        assert!(set.success, "set_u32 failed: {}", set.error);
+        let get = spell.get_string(key);
+        assert!(get.absent, "u32 must not be read as an empty string");
+    }
+
     #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
     fn test_set_host(spell: marine_test_env::spell::ModuleInterface) {
         let host_key = "h_str";
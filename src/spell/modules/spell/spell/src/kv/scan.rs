@@ -0,0 +1,306 @@
+/*
+ * Aqua Spell Service
+ *
+ * Copyright (C) 2024 Fluence DAO
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation version 3 of the
+ * License.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use marine_rs_sdk::marine;
+
+use crate::auth::guard_kv_write;
+use fluence_spell_dtos::value::{KeyInfo, KeyString, KeyStringsValue, KeysValue, U32Value};
+
+use crate::misc::{fetch_rows, in_transaction, into_page};
+use crate::schema::db;
+
+#[marine]
+/// List keys starting with `prefix` along with the type of their values, sorted by name.
+/// Use `limit` and `offset` to read the keys page by page.
+pub fn list_keys(prefix: &str, limit: u32, offset: u32) -> KeysValue {
+    let result: eyre::Result<(Vec<KeyInfo>, bool)> = try {
+        let conn = db();
+        let mut statement = conn.prepare(
+            r#"
+            SELECT key,
+                   CASE
+                       WHEN MAX(list_order) >= 0 THEN 'list'
+                       ELSE MAX(value_type)
+                   END
+              FROM kv
+             WHERE substr(key, 1, length(?)) = ?
+               AND (expires_at IS NULL OR expires_at > strftime('%s', 'now'))
+          GROUP BY key
+         UNION ALL
+            SELECT DISTINCT key, 'map'
+              FROM kv_map
+             WHERE substr(key, 1, length(?)) = ?
+          ORDER BY key
+             LIMIT ? OFFSET ?
+            "#,
+        )?;
+        statement.bind(1, prefix)?;
+        statement.bind(2, prefix)?;
+        statement.bind(3, prefix)?;
+        statement.bind(4, prefix)?;
+        statement.bind(5, limit as i64 + 1)?;
+        statement.bind(6, offset as i64)?;
+        let keys = fetch_rows(statement, |statement| {
+            Ok(Some(KeyInfo {
+                key: statement.read::<String>(0)?,
+                value_type: statement.read::<String>(1)?,
+            }))
+        });
+
+        into_page(keys, limit)
+    };
+
+    result.into()
+}
+
+#[marine]
+/// Get string values of the keys starting with `prefix`, sorted by key.
+/// Fields of maps are returned as `key.field`, the way `set_json` names nested fields.
+/// Values of other types and lists are skipped.
+/// Use `limit` and `offset` to read the values page by page.
+pub fn get_strings_by_prefix(prefix: &str, limit: u32, offset: u32) -> KeyStringsValue {
+    let result: eyre::Result<(Vec<KeyString>, bool)> = try {
+        let conn = db();
+        let mut statement = conn.prepare(
+            r#"
+            SELECT key, string
+              FROM kv
+             WHERE substr(key, 1, length(?)) = ?
+               AND value_type IN ('string', 'json')
+               AND list_order == -1
+               AND (expires_at IS NULL OR expires_at > strftime('%s', 'now'))
+         UNION ALL
+            SELECT key || '.' || field AS key, value
+              FROM kv_map
+             WHERE substr(key || '.' || field, 1, length(?)) = ?
+          ORDER BY key
+             LIMIT ? OFFSET ?
+            "#,
+        )?;
+        statement.bind(1, prefix)?;
+        statement.bind(2, prefix)?;
+        statement.bind(3, prefix)?;
+        statement.bind(4, prefix)?;
+        statement.bind(5, limit as i64 + 1)?;
+        statement.bind(6, offset as i64)?;
+        let entries = fetch_rows(statement, |statement| {
+            Ok(Some(KeyString {
+                key: statement.read::<String>(0)?,
+                value: statement.read::<String>(1)?,
+            }))
+        });
+
+        into_page(entries, limit)
+    };
+
+    result.into()
+}
+
+#[marine]
+/// Delete all keys starting with `prefix` and return the number of deleted keys.
+/// Write permissions are checked for every matched key, and nothing is deleted if any of them is forbidden.
+pub fn remove_keys_by_prefix(prefix: &str) -> U32Value {
+    let result: eyre::Result<Option<u32>> = try {
+        let conn = db();
+        in_transaction(&conn, |conn| {
+            let mut select = conn.prepare(
+                r#"
+                SELECT key FROM kv WHERE substr(key, 1, length(?)) = ?
+                 UNION
+                SELECT key FROM kv_map WHERE substr(key, 1, length(?)) = ?
+                "#,
+            )?;
+            for idx in 1..=4 {
+                select.bind(idx, prefix)?;
+            }
+            let keys = fetch_rows(select, |statement| Ok(Some(statement.read::<String>(0)?)));
+            for key in &keys {
+                guard_kv_write(key, None)?;
+            }
+
+            for table in ["kv", "kv_map"] {
+                let mut delete = conn.prepare(f!(
+                    "DELETE FROM {table} WHERE substr(key, 1, length(?)) = ?"
+                ))?;
+                delete.bind(1, prefix)?;
+                delete.bind(2, prefix)?;
+                delete.next()?;
+            }
+
+            Ok(Some(keys.len() as u32))
+        })?
+    };
+
+    result.into()
+}
+
+#[test_env_helpers::after_each]
+#[cfg(test)]
+mod tests {
+    use marine_rs_sdk::CallParameters;
+    use marine_rs_sdk::ParticleParameters;
+    use marine_rs_sdk_test::marine_test;
+
+    const DB_FILE: &str = "./tests_artifacts/spell.sqlite";
+
+    #[ctor::ctor]
+    /// usage of 'ctor' makes this function run only once
+    fn before_all_tests() {
+        std::fs::remove_file(DB_FILE).ok();
+    }
+
+    /// after_each macro copy-pastes this function into every test
+    fn after_each() {
+        std::fs::remove_file(DB_FILE).ok();
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_list_keys(spell: marine_test_env::spell::ModuleInterface) {
+        let cp = || spell_call_params();
+        spell.set_string_cp("w_str".into(), "a".into(), cp());
+        spell.set_u32_cp("w_num".into(), 1, cp());
+        spell.set_bool_cp("w_flag".into(), true, cp());
+        spell.list_push_string_cp("w_list".into(), "a".into(), cp());
+        spell.list_push_string_cp("w_list".into(), "b".into(), cp());
+        spell.set_string_cp("other".into(), "a".into(), cp());
+        // `_` must not be treated as a wildcard
+        spell.set_string_cp("wXstr".into(), "a".into(), cp());
+        spell.map_set_cp("w_map".into(), "f".into(), "a".into(), cp());
+        spell.map_set_cp("w_map".into(), "g".into(), "b".into(), cp());
+
+        let list = spell.list_keys("w_".into(), 10, 0);
+        assert!(list.success, "list_keys failed: {}", list.error);
+        assert!(!list.has_more);
+        let keys: Vec<_> = list
+            .keys
+            .iter()
+            .map(|k| (k.key.as_str(), k.value_type.as_str()))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                ("w_flag", "bool"),
+                ("w_list", "list"),
+                ("w_map", "map"),
+                ("w_num", "u32"),
+                ("w_str", "string")
+            ]
+        );
+
+        let page = spell.list_keys("w_".into(), 3, 0);
+        assert!(
+            page.has_more,
+            "there must be more keys after the first page"
+        );
+        assert_eq!(page.keys.len(), 3);
+        let page = spell.list_keys("w_".into(), 3, 3);
+        assert!(!page.has_more, "the second page must be the last");
+        assert_eq!(page.keys.len(), 2);
+        assert_eq!(page.keys[1].key, "w_str");
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_get_strings_by_prefix(spell: marine_test_env::spell::ModuleInterface) {
+        let cp = || spell_call_params();
+        spell.set_string_cp("peer_1".into(), "a".into(), cp());
+        spell.set_string_cp("peer_2".into(), "b".into(), cp());
+        spell.set_u32_cp("peer_3".into(), 3, cp());
+        spell.list_push_string_cp("peer_4".into(), "c".into(), cp());
+        spell.map_set_cp("peer_5".into(), "addr".into(), "d".into(), cp());
+
+        let get = spell.get_strings_by_prefix("peer_".into(), 10, 0);
+        assert!(get.success, "get_strings_by_prefix failed: {}", get.error);
+        let entries: Vec<_> = get
+            .entries
+            .iter()
+            .map(|e| (e.key.as_str(), e.value.as_str()))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![("peer_1", "a"), ("peer_2", "b"), ("peer_5.addr", "d")]
+        );
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_remove_keys_by_prefix(spell: marine_test_env::spell::ModuleInterface) {
+        let cp = || spell_call_params();
+        spell.set_string_cp("hw_peer_1".into(), "a".into(), cp());
+        spell.list_push_string_cp("hw_peer_2".into(), "b".into(), cp());
+        spell.set_string_cp("hw_other".into(), "c".into(), cp());
+        spell.map_set_cp("hw_peer_3".into(), "f".into(), "d".into(), cp());
+
+        let remove = spell.remove_keys_by_prefix_cp("hw_peer_".into(), worker_call_params());
+        assert!(
+            remove.success,
+            "remove_keys_by_prefix failed: {}",
+            remove.error
+        );
+        assert_eq!(remove.value, 3);
+
+        let list = spell.list_keys("hw_".into(), 10, 0);
+        assert_eq!(list.keys.len(), 1);
+        assert_eq!(list.keys[0].key, "hw_other");
+        assert!(spell.map_get_all("hw_peer_3".into()).entries.is_empty());
+    }
+
+    #[marine_test(config_path = "../../tests_artifacts/Config.toml")]
+    fn test_remove_keys_by_prefix_forbidden(spell: marine_test_env::spell::ModuleInterface) {
+        let cp = || spell_call_params();
+        spell.set_string_cp("w_a".into(), "a".into(), cp());
+        spell.set_string_cp("w_b".into(), "b".into(), cp());
+        spell.set_string_cp("wx".into(), "c".into(), cp());
+
+        // `wx` is private to the spell, so nothing must be removed
+        let remove = spell.remove_keys_by_prefix_cp("w".into(), worker_call_params());
+        assert!(!remove.success, "remove_keys_by_prefix must fail");
+
+        let list = spell.list_keys("w".into(), 10, 0);
+        assert_eq!(list.keys.len(), 3);
+    }
+
+    fn spell_call_params() -> CallParameters {
+        CallParameters {
+            particle: ParticleParameters {
+                init_peer_id: "worker-id".to_string(),
+                id: "spell_spell-id_0".to_string(),
+                ..<_>::default()
+            },
+            service_creator_peer_id: "worker-id".to_string(),
+            service_id: "spell-id".to_string(),
+            worker_id: "worker-id".to_string(),
+            host_id: "host-id".to_string(),
+            tetraplets: vec![],
+        }
+    }
+
+    fn worker_call_params() -> CallParameters {
+        CallParameters {
+            particle: ParticleParameters {
+                init_peer_id: "worker-id".to_string(),
+                id: "some-particle".to_string(),
+                ..<_>::default()
+            },
+            service_creator_peer_id: "worker-id".to_string(),
+            service_id: "spell-id".to_string(),
+            worker_id: "worker-id".to_string(),
+            host_id: "host-id".to_string(),
+            tetraplets: vec![],
+        }
+    }
+}
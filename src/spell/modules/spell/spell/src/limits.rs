@@ -0,0 +1,299 @@
+/*
+ * Aqua Spell Service
+ *
+ * Copyright (C) 2024 Fluence DAO
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation version 3 of the
+ * License.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use marine_rs_sdk::marine;
+use marine_sqlite_connector::Connection;
+
+use fluence_spell_dtos::error::SpellError::SetLimitsForbidden;
+use fluence_spell_dtos::limits::{Limits, LimitsValue};
+use fluence_spell_dtos::value::UnitValue;
+
+use crate::auth::is_by_creator;
+use crate::error_handling::expire_errors;
+use crate::misc::{in_transaction, read_config, write_config, write_limit};
+use crate::schema::db;
+
+/// Deletes the oldest rows above the limits and updates the counters.
+/// The limit triggers remove only one row per insert, so they can't shrink the tables themselves.
+fn trim(conn: &Connection, limits: &Limits) -> eyre::Result<()> {
+    let mut logs = conn.prepare(
+        r#"
+        DELETE FROM logs
+         WHERE id NOT IN (SELECT id FROM logs ORDER BY timestamp DESC, id DESC LIMIT ?)
+        "#,
+    )?;
+    logs.bind(1, limits.max_logs as i64)?;
+    logs.next()?;
+
+    // `max_mailbox` caps every topic without a limit of its own, see `set_mailbox_topic_limit`
+    let mut mailbox = conn.prepare(
+        r#"
+        DELETE FROM mailbox
+         WHERE (SELECT COUNT(*) FROM mailbox AS newer
+                 WHERE newer.topic = mailbox.topic
+                   AND (newer.timestamp > mailbox.timestamp
+                        OR (newer.timestamp = mailbox.timestamp AND newer.id > mailbox.id)))
+               >= COALESCE(
+                   (SELECT value FROM config_table WHERE parameter = 'max_mailbox:' || mailbox.topic),
+                   ?
+               )
+        "#,
+    )?;
+    mailbox.bind(1, limits.max_mailbox as i64)?;
+    mailbox.next()?;
+
+    // errors and `count_particles` are updated by the `clear_errors` trigger
+    let mut particles = conn.prepare(
+        r#"
+        DELETE FROM particles
+         WHERE particle_id NOT IN (
+               SELECT particle_id FROM particles ORDER BY timestamp DESC LIMIT ?
+         )
+        "#,
+    )?;
+    particles.bind(1, limits.max_particles as i64)?;
+    particles.next()?;
+
+    let mut runs = conn.prepare(
+        r#"
+        DELETE FROM runs
+         WHERE id NOT IN (SELECT id FROM runs ORDER BY id DESC LIMIT ?)
+        "#,
+    )?;
+    runs.bind(1, limits.max_runs as i64)?;
+    runs.next()?;
+
+    conn.execute(
+        r#"
+        UPDATE config_table SET value = (SELECT COUNT(*) FROM logs) WHERE parameter = 'count_logs';
+        UPDATE config_table SET value = (SELECT COUNT(*) FROM mailbox) WHERE parameter = 'count_mailbox';
+        UPDATE config_table SET value = (SELECT COUNT(*) FROM runs) WHERE parameter = 'count_runs';
+        "#,
+    )?;
+
+    Ok(())
+}
+
+#[marine]
+/// Set how many latest logs, mailbox messages, failed particles and runs the spell keeps.
+/// Rows above the new limits are deleted right away, the oldest first, as well as the expired errors.
+pub fn set_limits(limits: Limits) -> UnitValue {
+    if !is_by_creator() {
+        return SetLimitsForbidden.into();
+    }
+
+    let result: eyre::Result<()> = try {
+        let conn = db();
+        in_transaction(&conn, |conn| {
+            write_limit(conn, "max_logs", limits.max_logs as u64)?;
+            write_limit(conn, "max_mailbox", limits.max_mailbox as u64)?;
+            write_limit(conn, "max_particles", limits.max_particles as u64)?;
+            write_limit(conn, "max_runs", limits.max_runs as u64)?;
+            write_config(conn, "max_error_age_sec", limits.max_error_age_sec as u64)?;
+            trim(conn, &limits)?;
+            expire_errors(conn)
+        })?
+    };
+
+    result.into()
+}
+
+#[marine]
+pub fn get_limits() -> LimitsValue {
+    let result: eyre::Result<Limits> = try {
+        let conn = db();
+        Limits {
+            max_logs: read_config(&conn, "max_logs")? as u32,
+            max_mailbox: read_config(&conn, "max_mailbox")? as u32,
+            max_particles: read_config(&conn, "max_particles")? as u32,
+            max_runs: read_config(&conn, "max_runs")? as u32,
+            max_error_age_sec: read_config(&conn, "max_error_age_sec")? as u32,
+        }
+    };
+
+    result.into()
+}
+
+#[test_env_helpers::after_each]
+#[cfg(test)]
+mod tests {
+    use marine_rs_sdk::CallParameters;
+    use marine_rs_sdk::ParticleParameters;
+    use marine_rs_sdk_test::marine_test;
+
+    use crate::schema::{
+        DEFAULT_MAX_ERR_PARTICLES, DEFAULT_MAX_LOGS, DEFAULT_MAX_MAILBOX, DEFAULT_MAX_RUNS,
+    };
+
+    const DB_FILE: &str = "./tests_artifacts/spell.sqlite";
+
+    #[ctor::ctor]
+    /// usage of 'ctor' makes this function run only once
+    fn before_all_tests() {
+        std::fs::remove_file(DB_FILE).ok();
+    }
+
+    /// after_each macro copy-pastes this function into every test
+    fn after_each() {
+        std::fs::remove_file(DB_FILE).ok();
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_default_limits(spell: marine_test_env::spell::ModuleInterface) {
+        let limits = spell.get_limits();
+        assert!(limits.success, "get_limits failed: {}", limits.error);
+        assert_eq!(limits.limits.max_logs as usize, DEFAULT_MAX_LOGS);
+        assert_eq!(limits.limits.max_mailbox as usize, DEFAULT_MAX_MAILBOX);
+        assert_eq!(
+            limits.limits.max_particles as usize,
+            DEFAULT_MAX_ERR_PARTICLES
+        );
+        assert_eq!(limits.limits.max_runs as usize, DEFAULT_MAX_RUNS);
+        assert_eq!(limits.limits.max_error_age_sec, 0);
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_set_limits_trims(spell: marine_test_env::spell::ModuleInterface) {
+        use marine_test_env::spell::Limits;
+
+        for i in 0..10 {
+            let store = spell.store_log_cp(format!("log {i}"), spell_call_params());
+            assert!(store.success, "store_log failed: {}", store.error);
+            let push = spell.push_mailbox_cp(format!("message {i}"), spell_call_params());
+            assert!(push.success, "push_mailbox failed: {}", push.error);
+        }
+
+        let limits = Limits {
+            max_logs: 3,
+            max_mailbox: 2,
+            max_particles: 20,
+            max_runs: 50,
+            max_error_age_sec: 0,
+        };
+        let set = spell.set_limits_cp(limits, spell_call_params());
+        assert!(set.success, "set_limits failed: {}", set.error);
+
+        let get = spell.get_limits();
+        assert_eq!(get.limits.max_logs, 3);
+        assert_eq!(get.limits.max_mailbox, 2);
+        assert_eq!(get.limits.max_particles, 20);
+
+        let logs = spell.get_logs().logs;
+        let logs: Vec<_> = logs.into_iter().map(|log| log.message).collect();
+        assert_eq!(
+            logs,
+            vec!["log 7", "log 8", "log 9"],
+            "the latest logs must be kept"
+        );
+        let messages = spell.get_mailbox().messages;
+        let messages: Vec<_> = messages.into_iter().map(|m| m.message).collect();
+        assert_eq!(messages, vec!["message 9", "message 8"]);
+
+        // the limits hold for the new rows too
+        for i in 10..15 {
+            let _ = spell.store_log_cp(format!("log {i}"), spell_call_params());
+        }
+        assert_eq!(spell.get_logs().logs.len(), 3);
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_set_limits_invalid(spell: marine_test_env::spell::ModuleInterface) {
+        use marine_test_env::spell::Limits;
+
+        let limits = Limits {
+            max_logs: 5000,
+            max_mailbox: 0,
+            max_particles: 20,
+            max_runs: 50,
+            max_error_age_sec: 0,
+        };
+        let set = spell.set_limits_cp(limits, spell_call_params());
+        assert!(!set.success, "zero limits must be rejected");
+
+        let limits = Limits {
+            max_logs: 5000,
+            max_mailbox: 20,
+            max_particles: 20,
+            max_runs: 50,
+            max_error_age_sec: 0,
+        };
+        let set = spell.set_limits_cp(limits, other_call_params());
+        assert!(!set.success, "only the creator can set limits");
+
+        let get = spell.get_limits();
+        assert_eq!(get.limits.max_logs as usize, DEFAULT_MAX_LOGS);
+        assert_eq!(get.limits.max_mailbox as usize, DEFAULT_MAX_MAILBOX);
+    }
+
+    #[marine_test(spell(config_path = "../tests_artifacts/Config.toml"))]
+    fn test_limits_persist() {
+        use marine_test_env::spell::Limits;
+
+        let mut spell = marine_test_env::spell::ServiceInterface::new();
+        let limits = Limits {
+            max_logs: 5000,
+            max_mailbox: 20,
+            max_particles: 10,
+            max_runs: 50,
+            max_error_age_sec: 0,
+        };
+        let set = spell.set_limits_cp(limits, spell_call_params());
+        assert!(set.success, "set_limits failed: {}", set.error);
+        let store = spell.store_log_cp("log".into(), spell_call_params());
+        assert!(store.success, "store_log failed: {}", store.error);
+
+        // restart the service on the same database
+        let mut spell = marine_test_env::spell::ServiceInterface::new();
+        let get = spell.get_limits();
+        assert_eq!(get.limits.max_logs, 5000);
+        assert_eq!(get.limits.max_mailbox, 20);
+        assert_eq!(get.limits.max_particles, 10);
+        assert_eq!(spell.get_logs().logs.len(), 1);
+    }
+
+    fn spell_call_params() -> CallParameters {
+        CallParameters {
+            particle: ParticleParameters {
+                init_peer_id: "worker-id".to_string(),
+                id: "spell_spell-id_0".to_string(),
+                ..<_>::default()
+            },
+            service_creator_peer_id: "worker-id".to_string(),
+            service_id: "spell-id".to_string(),
+            worker_id: "worker-id".to_string(),
+            host_id: "host-id".to_string(),
+            tetraplets: vec![],
+        }
+    }
+
+    fn other_call_params() -> CallParameters {
+        CallParameters {
+            particle: ParticleParameters {
+                init_peer_id: "other-worker-id".to_string(),
+                id: "some-particle".to_string(),
+                ..<_>::default()
+            },
+            service_creator_peer_id: "worker-id".to_string(),
+            service_id: "spell-id".to_string(),
+            worker_id: "worker-id".to_string(),
+            host_id: "host-id".to_string(),
+            tetraplets: vec![],
+        }
+    }
+}
@@ -17,17 +17,97 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use marine_rs_sdk::marine;
+use eyre::Context;
+use marine_rs_sdk::{marine, ParticleParameters};
+use marine_sqlite_connector::{Connection, Statement};
+use serde_json::{Map, Value as JValue};
 
-use fluence_spell_dtos::value::{GetLogsResult, Log, UnitValue};
+use fluence_spell_dtos::value::{GetLogsResult, Log, LogFilter, LogQuery, LogsPage, UnitValue};
 
 use crate::auth::is_by_spell;
-use crate::misc::fetch_rows;
+use crate::misc::{fetch_rows, into_page, read_u64};
 use crate::schema::db;
 
+/// Log levels from the least to the most severe
+const LOG_LEVELS: [&str; 5] = ["trace", "debug", "info", "warn", "error"];
+const DEFAULT_LOG_LEVEL: &str = "info";
+const TAG_SEPARATOR: char = ',';
+/// Columns read by `read_log`
+pub const LOG_COLUMNS: &str = "id, timestamp, log, level, tags, particle_id, particle_timestamp";
+
+fn level_rank(level: &str) -> eyre::Result<usize> {
+    LOG_LEVELS
+        .iter()
+        .position(|known| *known == level)
+        .ok_or_else(|| eyre::eyre!("unknown log level `{level}`, expected one of {LOG_LEVELS:?}"))
+}
+
+fn insert_log(
+    conn: &Connection,
+    particle: &ParticleParameters,
+    level: &str,
+    log: &str,
+    tags: &[String],
+    structured: bool,
+) -> eyre::Result<()> {
+    level_rank(level)?;
+    for tag in tags {
+        if tag.is_empty() || tag.contains(TAG_SEPARATOR) {
+            Err(eyre::eyre!(
+                "tag '{tag}' must be non-empty and must not contain '{TAG_SEPARATOR}'"
+            ))?;
+        }
+    }
+
+    let mut statement = conn.prepare(
+        r#"
+        INSERT INTO logs (log, level, tags, structured, particle_id, particle_timestamp)
+        VALUES (?, ?, ?, ?, ?, ?)
+        "#,
+    )?;
+    statement.bind(1, log)?;
+    statement.bind(2, level)?;
+    statement.bind(4, structured as i64)?;
+    statement.bind(5, particle.id.as_str())?;
+    statement.bind(6, particle.timestamp as i64)?;
+    if tags.is_empty() {
+        statement.bind(3, ())?;
+    } else {
+        let sep = TAG_SEPARATOR.to_string();
+        statement.bind(3, f!("{sep}{}{sep}", tags.join(&sep)).as_str())?;
+    }
+    statement.next()?;
+
+    Ok(())
+}
+
+pub fn read_log(statement: &mut Statement) -> eyre::Result<Option<Log>> {
+    let tags = statement.read::<Option<String>>(4)?.unwrap_or_default();
+    Ok(Some(Log {
+        id: statement.read::<i64>(0)? as u64,
+        timestamp: statement.read::<i64>(1)? as u64,
+        message: statement.read::<String>(2)?,
+        level: statement.read::<String>(3)?,
+        tags: tags
+            .split(TAG_SEPARATOR)
+            .filter(|tag| !tag.is_empty())
+            .map(String::from)
+            .collect(),
+        particle_id: statement.read::<Option<String>>(5)?.unwrap_or_default(),
+        particle_timestamp: statement.read::<Option<i64>>(6)?.unwrap_or_default() as u64,
+    }))
+}
+
 #[marine]
-/// Push a log to the db. It keeps `DEFAULT_MAX_LOGS` latest logs.
+/// Push a log to the db with the `info` level. It keeps `max_logs` latest logs, see `set_limits`.
 pub fn store_log(log: String) -> UnitValue {
+    store_log_with_level(DEFAULT_LOG_LEVEL.to_string(), log, vec![])
+}
+
+#[marine]
+/// Push a log with the level (`trace`, `debug`, `info`, `warn` or `error`) and tags to the db.
+/// Tags must be non-empty and must not contain ','.
+pub fn store_log_with_level(level: String, log: String, tags: Vec<String>) -> UnitValue {
     let call_parameters = marine_rs_sdk::get_call_parameters();
 
     // We want to prevent anyone except this spell to store logs to its kv
@@ -35,35 +115,189 @@ pub fn store_log(log: String) -> UnitValue {
         return UnitValue::error("store_log can be called only by the associated spell script");
     }
 
-    let result: eyre::Result<()> = try {
-        let conn = db();
-        let mut statement = conn.prepare(r#"INSERT INTO logs (log) VALUES (?)"#)?;
-        statement.bind(1, log.as_str())?;
+    let result = insert_log(&db(), &call_parameters.particle, &level, &log, &tags, false);
+
+    match result {
+        Ok(_) => UnitValue::ok(),
+        Err(e) => UnitValue::error(format!("store_log error: {}", e)),
+    }
+}
 
-        statement.next()?;
+#[marine]
+/// Push a log with the `info` level holding a JSON object, its top-level fields can be queried with `query_logs`.
+/// The object is stored as text, so it's returned by `get_logs` as well.
+pub fn store_structured_log(fields_json: String) -> UnitValue {
+    let call_parameters = marine_rs_sdk::get_call_parameters();
+
+    // We want to prevent anyone except this spell to store logs to its kv
+    if !is_by_spell(&call_parameters) {
+        return UnitValue::error(
+            "store_structured_log can be called only by the associated spell script",
+        );
+    }
+
+    let result: eyre::Result<()> = try {
+        let fields: Map<String, JValue> = serde_json::from_str(&fields_json)
+            .context("passed string must represent a JSON object")?;
+        let log = JValue::Object(fields).to_string();
+        insert_log(
+            &db(),
+            &call_parameters.particle,
+            DEFAULT_LOG_LEVEL,
+            &log,
+            &[],
+            true,
+        )?
     };
 
     match result {
         Ok(_) => UnitValue::ok(),
-        Err(e) => UnitValue::error(format!("store_log error: {}", e)),
+        Err(e) => UnitValue::error(format!("store_structured_log error: {}", e)),
     }
 }
 
 #[marine]
 /// Get all logs ordered by timestamp ascending.
 pub fn get_logs() -> GetLogsResult {
+    get_logs_filtered(LogFilter::default())
+}
+
+#[marine]
+/// Get the logs matching the filter ordered by timestamp ascending.
+pub fn get_logs_filtered(filter: LogFilter) -> GetLogsResult {
     let result: eyre::Result<Vec<Log>> = try {
+        let mut conditions = vec![];
+        if !filter.min_level.is_empty() {
+            // the levels are known constants, so they can be inlined into the query
+            let levels = LOG_LEVELS[level_rank(&filter.min_level)?..]
+                .iter()
+                .map(|level| f!("'{level}'"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            conditions.push(f!("level IN ({levels})"));
+        }
+        if !filter.tag.is_empty() {
+            conditions.push("instr(tags, ?) > 0".to_string());
+        }
+        if filter.from_timestamp > 0 {
+            conditions.push(f!("timestamp >= {filter.from_timestamp}"));
+        }
+        if filter.to_timestamp > 0 {
+            conditions.push(f!("timestamp <= {filter.to_timestamp}"));
+        }
+        let filter_clause = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+
+        let conn = db();
+        let mut statement = conn.prepare(f!(
+            "SELECT {LOG_COLUMNS} FROM logs {filter_clause} ORDER BY timestamp ASC, id ASC"
+        ))?;
+        if !filter.tag.is_empty() {
+            statement.bind(1, f!("{TAG_SEPARATOR}{filter.tag}{TAG_SEPARATOR}").as_str())?;
+        }
+
+        fetch_rows(statement, read_log)
+    };
+
+    result.into()
+}
+
+fn logs_after(conn: &Connection, after_id: u64, limit: u32) -> eyre::Result<(Vec<Log>, u64, bool)> {
+    let mut statement = conn.prepare(f!(
+        "SELECT {LOG_COLUMNS} FROM logs WHERE id > ? ORDER BY id ASC LIMIT ?"
+    ))?;
+    statement.bind(1, after_id as i64)?;
+    statement.bind(2, limit as i64 + 1)?;
+    let (logs, has_more) = into_page(fetch_rows(statement, read_log), limit);
+    let cursor = logs.last().map_or(after_id, |log| log.id);
+
+    Ok((logs, cursor, has_more))
+}
+
+#[marine]
+/// Get up to `limit` logs stored after the log with `after_id`, the oldest first.
+/// Start with `after_id` = 0 and pass the returned `cursor` to the next call to tail the logs.
+pub fn get_logs_since(after_id: u64, limit: u32) -> LogsPage {
+    logs_after(&db(), after_id, limit).into()
+}
+
+#[marine]
+/// Get up to `limit` logs stored at `timestamp` or later, the oldest first.
+/// `timestamp` is unix time in seconds. The returned `cursor` is a log id as in `get_logs_since`,
+/// so pass it to `get_logs_since` to continue the reading.
+pub fn get_logs_since_timestamp(timestamp: u64, limit: u32) -> LogsPage {
+    let result: eyre::Result<(Vec<Log>, u64, bool)> = try {
         let conn = db();
-        let statement =
-            conn.prepare(r#"SELECT timestamp, log FROM logs ORDER BY timestamp ASC, id ASC"#)?;
-        let logs: Vec<Log> = fetch_rows(statement, |statement| {
-            Ok(Some(Log {
-                timestamp: statement.read::<i64>(0)? as u64,
-                message: statement.read::<String>(1)?,
-            }))
-        });
-
-        logs
+        let after_id = read_u64(
+            &conn,
+            &f!("SELECT MAX(id) FROM logs WHERE timestamp < {timestamp}"),
+        )?;
+        logs_after(&conn, after_id, limit)?
+    };
+
+    result.into()
+}
+
+#[marine]
+/// Get a page of `limit` logs skipping `offset` ones.
+/// `order` is `asc` to start from the oldest logs or `desc` to start from the latest ones.
+pub fn get_logs_page(offset: u32, limit: u32, order: String) -> LogsPage {
+    let result: eyre::Result<(Vec<Log>, u64, bool)> = try {
+        let order = match order.as_str() {
+            "asc" => "ASC",
+            "desc" => "DESC",
+            _ => Err(eyre::eyre!("order must be `asc` or `desc`, got `{order}`"))?,
+        };
+        let conn = db();
+        let mut statement = conn.prepare(f!(
+            "SELECT {LOG_COLUMNS} FROM logs ORDER BY id {order} LIMIT ? OFFSET ?"
+        ))?;
+        statement.bind(1, limit as i64 + 1)?;
+        statement.bind(2, offset as i64)?;
+        let (logs, has_more) = into_page(fetch_rows(statement, read_log), limit);
+        let cursor = offset as u64 + logs.len() as u64;
+
+        (logs, cursor, has_more)
+    };
+
+    result.into()
+}
+
+#[marine]
+/// Get the structured logs matching the query ordered by timestamp ascending.
+/// Other logs are skipped.
+pub fn query_logs(query: LogQuery) -> GetLogsResult {
+    let result: eyre::Result<Vec<Log>> = try {
+        let exists = query.exists;
+        let mut equals = vec![];
+        for condition in query.equals {
+            let value: JValue = serde_json::from_str(&condition.value).context(format!(
+                "value of the field '{}' must be JSON",
+                condition.field
+            ))?;
+            equals.push((condition.field, value));
+        }
+
+        let conn = db();
+        let statement = conn.prepare(f!(
+            "SELECT {LOG_COLUMNS} FROM logs WHERE structured = 1 ORDER BY timestamp ASC, id ASC"
+        ))?;
+        let logs = fetch_rows(statement, read_log);
+
+        logs.into_iter()
+            .filter(|log| {
+                let Ok(fields) = serde_json::from_str::<Map<String, JValue>>(&log.message) else {
+                    return false;
+                };
+                exists.iter().all(|field| fields.contains_key(field))
+                    && equals
+                        .iter()
+                        .all(|(field, value)| fields.get(field) == Some(value))
+            })
+            .collect()
     };
 
     result.into()
@@ -128,6 +362,190 @@ mod tests {
         assert_eq!(logs[0].message, log1);
         assert_eq!(logs[1].message, log2);
         assert!(logs[0].timestamp <= logs[1].timestamp);
+        assert_eq!(logs[0].level, "info");
+        assert!(logs[0].tags.is_empty());
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_store_log_with_level(spell: marine_test_env::spell::ModuleInterface) {
+        use marine_test_env::spell::LogFilter;
+
+        let service_id = Uuid::new_v4();
+        let cp = cp(service_id.to_string(), format!("spell_{}", service_id));
+        let tags = |tags: &[&str]| tags.iter().map(|tag| tag.to_string()).collect();
+        let filter = |min_level: &str, tag: &str, from_timestamp: u64| LogFilter {
+            min_level: min_level.to_string(),
+            tag: tag.to_string(),
+            from_timestamp,
+            to_timestamp: 0,
+        };
+
+        for (level, log, log_tags) in [
+            ("debug", "connecting", vec!["net"]),
+            ("warn", "slow peer", vec!["net", "peer"]),
+            ("error", "no deal", vec!["deal"]),
+        ] {
+            let store = spell.store_log_with_level_cp(
+                level.into(),
+                log.into(),
+                tags(&log_tags),
+                cp.clone(),
+            );
+            assert!(store.success, "{}", store.error);
+        }
+        let store = spell.store_log_cp("plain".into(), cp.clone());
+        assert!(store.success, "{}", store.error);
+
+        let invalid =
+            spell.store_log_with_level_cp("fatal".into(), "log".into(), vec![], cp.clone());
+        assert!(!invalid.success, "unknown levels must be rejected");
+        let invalid =
+            spell.store_log_with_level_cp("info".into(), "log".into(), tags(&["a,b"]), cp.clone());
+        assert!(!invalid.success, "tags with ',' must be rejected");
+
+        let messages = |logs: Vec<marine_test_env::spell::Log>| -> Vec<String> {
+            logs.into_iter().map(|log| log.message).collect()
+        };
+        let all = spell.get_logs_filtered(filter("", "", 0));
+        assert!(all.success, "get_logs_filtered failed: {}", all.error);
+        assert_eq!(all.logs.len(), 4);
+        assert_eq!(
+            messages(spell.get_logs_filtered(filter("warn", "", 0)).logs),
+            vec!["slow peer", "no deal"]
+        );
+        assert_eq!(
+            messages(spell.get_logs_filtered(filter("", "net", 0)).logs),
+            vec!["connecting", "slow peer"]
+        );
+        assert_eq!(
+            messages(spell.get_logs_filtered(filter("info", "net", 0)).logs),
+            vec!["slow peer"]
+        );
+        let partial_tag = spell.get_logs_filtered(filter("", "ne", 0));
+        assert!(partial_tag.logs.is_empty(), "tags must match exactly");
+        let future = spell.get_logs_filtered(filter("", "", u32::MAX as u64));
+        assert!(future.logs.is_empty());
+        let unknown_level = spell.get_logs_filtered(filter("fatal", "", 0));
+        assert!(!unknown_level.success, "unknown levels must be rejected");
+
+        let logs = spell.get_logs().logs;
+        assert_eq!(logs[1].level, "warn");
+        assert_eq!(logs[1].tags, vec!["net", "peer"]);
+        assert_eq!(logs[3].level, "info");
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_logs_pages(spell: marine_test_env::spell::ModuleInterface) {
+        let service_id = Uuid::new_v4();
+        let cp = cp(service_id.to_string(), format!("spell_{}", service_id));
+        for i in 0..5 {
+            let store = spell.store_log_cp(format!("log {i}"), cp.clone());
+            assert!(store.success, "{}", store.error);
+        }
+
+        let page = spell.get_logs_since(0, 3);
+        assert!(page.success, "get_logs_since failed: {}", page.error);
+        let messages: Vec<_> = page.logs.iter().map(|log| log.message.as_str()).collect();
+        assert_eq!(messages, vec!["log 0", "log 1", "log 2"]);
+        assert!(page.has_more);
+        assert_eq!(page.cursor, page.logs[2].id);
+
+        let page = spell.get_logs_since(page.cursor, 3);
+        let messages: Vec<_> = page.logs.iter().map(|log| log.message.as_str()).collect();
+        assert_eq!(messages, vec!["log 3", "log 4"]);
+        assert!(!page.has_more);
+
+        // nothing new, the cursor stays in place
+        let cursor = page.cursor;
+        let page = spell.get_logs_since(cursor, 3);
+        assert!(page.logs.is_empty());
+        assert_eq!(page.cursor, cursor);
+
+        let page = spell.get_logs_since_timestamp(0, 2);
+        assert!(
+            page.success,
+            "get_logs_since_timestamp failed: {}",
+            page.error
+        );
+        let messages: Vec<_> = page.logs.iter().map(|log| log.message.as_str()).collect();
+        assert_eq!(messages, vec!["log 0", "log 1"]);
+        assert!(page.has_more);
+        let page = spell.get_logs_since(page.cursor, 10);
+        assert_eq!(page.logs.len(), 3);
+        // no logs after the timestamp yet, the cursor points to the latest log
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let page = spell.get_logs_since_timestamp(now + 100, 3);
+        assert!(page.logs.is_empty());
+        assert_eq!(page.cursor, cursor);
+
+        let page = spell.get_logs_page(1, 2, "desc".into());
+        assert!(page.success, "get_logs_page failed: {}", page.error);
+        let messages: Vec<_> = page.logs.iter().map(|log| log.message.as_str()).collect();
+        assert_eq!(messages, vec!["log 3", "log 2"]);
+        assert!(page.has_more);
+        assert_eq!(page.cursor, 3);
+
+        let page = spell.get_logs_page(3, 2, "asc".into());
+        let messages: Vec<_> = page.logs.iter().map(|log| log.message.as_str()).collect();
+        assert_eq!(messages, vec!["log 3", "log 4"]);
+        assert!(!page.has_more);
+
+        let page = spell.get_logs_page(0, 2, "random".into());
+        assert!(!page.success, "unknown order must be rejected");
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_structured_logs(spell: marine_test_env::spell::ModuleInterface) {
+        use marine_test_env::spell::{LogFieldValue, LogQuery};
+
+        let service_id = Uuid::new_v4();
+        let cp = cp(service_id.to_string(), format!("spell_{}", service_id));
+        for log in [
+            r#"{"event": "deal", "status": "ok", "peers": 3}"#,
+            r#"{"event": "deal", "status": "failed"}"#,
+            r#"{"event": "ping"}"#,
+        ] {
+            let store = spell.store_structured_log_cp(log.into(), cp.clone());
+            assert!(store.success, "{}", store.error);
+        }
+        let store = spell.store_log_cp(r#"{"event": "deal"}"#.into(), cp.clone());
+        assert!(store.success, "{}", store.error);
+
+        let invalid = spell.store_structured_log_cp("[1, 2]".into(), cp.clone());
+        assert!(!invalid.success, "only JSON objects must be accepted");
+
+        let eq = |field: &str, value: &str| LogFieldValue {
+            field: field.to_string(),
+            value: value.to_string(),
+        };
+        let query = LogQuery {
+            exists: vec![],
+            equals: vec![eq("event", r#""deal""#)],
+        };
+        let logs = spell.query_logs(query);
+        assert!(logs.success, "query_logs failed: {}", logs.error);
+        assert_eq!(logs.logs.len(), 2, "plain logs must be skipped");
+
+        let query = LogQuery {
+            exists: vec!["peers".to_string()],
+            equals: vec![eq("event", r#""deal""#), eq("status", r#""ok""#)],
+        };
+        let logs = spell.query_logs(query).logs;
+        assert_eq!(logs.len(), 1);
+        let fields: serde_json::Value = serde_json::from_str(&logs[0].message).unwrap();
+        assert_eq!(fields["peers"], 3);
+
+        let query = LogQuery {
+            exists: vec![],
+            equals: vec![eq("event", "not json")],
+        };
+        assert!(!spell.query_logs(query).success, "values must be JSON");
+
+        // structured logs are still plain logs
+        assert_eq!(spell.get_logs().logs.len(), 4);
     }
 
     #[marine_test(config_path = "../tests_artifacts/Config.toml")]
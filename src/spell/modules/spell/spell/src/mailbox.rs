@@ -18,25 +18,77 @@
  */
 
 use marine_rs_sdk::marine;
-use marine_sqlite_connector::State;
+use marine_sqlite_connector::{Connection, State};
 
+use fluence_spell_dtos::error::SpellError::SetLimitsForbidden;
 use fluence_spell_dtos::value::{GetMailboxResult, MailboxMessage, PopMailboxResult, UnitValue};
 
-use crate::auth::is_by_spell;
-use crate::misc::fetch_rows;
+use crate::auth::{is_by_creator, is_by_spell};
+use crate::mailbox_policy::guard_mailbox_push;
+use crate::misc::{fetch_rows, in_transaction};
 use crate::schema::db;
 
+/// The topic of the messages pushed by `push_mailbox`, the other functions without a topic use it as well.
+/// `mailbox_ack` works for messages of any topic.
+pub const DEFAULT_TOPIC: &str = "";
+/// Columns read by `MailboxMessage::read`
+const MAILBOX_COLUMNS: &str = "init_peer_id, timestamp, message, id";
+/// Messages received with `mailbox_receive` are hidden until `visible_at`
+const IS_VISIBLE: &str =
+    "(visible_at IS NULL OR visible_at <= CAST(strftime('%s', 'now') AS INTEGER))";
+/// The message is received and its visibility timeout isn't over yet
+const IS_IN_FLIGHT: &str =
+    "(visible_at IS NOT NULL AND visible_at > CAST(strftime('%s', 'now') AS INTEGER))";
+
+fn oldest_visible(conn: &Connection, topic: &str) -> eyre::Result<Option<MailboxMessage>> {
+    let mut statement = conn.prepare(f!(
+        "SELECT {MAILBOX_COLUMNS} FROM mailbox WHERE topic = ? AND {IS_VISIBLE} ORDER BY id ASC LIMIT 1"
+    ))?;
+    statement.bind(1, topic)?;
+    if let State::Row = statement.next()? {
+        Ok(Some(MailboxMessage::read(&mut statement)?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Returns false if there is no message with the `id`
+fn delete_message(conn: &Connection, id: u64) -> eyre::Result<bool> {
+    let mut statement = conn.prepare("DELETE FROM mailbox WHERE id = ?")?;
+    statement.bind(1, id as i64)?;
+    statement.next()?;
+    Ok(conn.changes() > 0)
+}
+
 #[marine]
-/// Push a message to the mailbox. Mailbox keeps `DEFAULT_MAX_MAILBOX` latest messages.
+/// Push a message to the mailbox. Mailbox keeps `max_mailbox` latest messages, see `set_limits`.
+/// The sender must be allowed by the mailbox sender policy, see `set_mailbox_policy`.
 pub fn push_mailbox(message: String) -> UnitValue {
-    let init_peer_id = marine_rs_sdk::get_call_parameters().particle.init_peer_id;
+    push_mailbox_topic(DEFAULT_TOPIC.to_string(), message)
+}
+
+#[marine]
+/// Push a message to the topic of the mailbox. Every topic keeps `max_mailbox` latest messages
+/// unless it has a limit of its own, see `set_mailbox_topic_limit`.
+/// The mailbox keeps `max_mailbox_total` latest messages of all topics, see `set_mailbox_total_limit`.
+pub fn push_mailbox_topic(topic: String, message: String) -> UnitValue {
+    let call_parameters = marine_rs_sdk::get_call_parameters();
+    let init_peer_id = call_parameters.particle.init_peer_id.as_str();
     let result: eyre::Result<()> = try {
         let conn = db();
-        let mut statement =
-            conn.prepare(r#" INSERT INTO mailbox (init_peer_id, message) VALUES (?, ?)"#)?;
-        statement.bind(1, init_peer_id.as_str())?;
-        statement.bind(2, message.as_str())?;
-        statement.next()?;
+        in_transaction(&conn, |conn| {
+            guard_mailbox_push(conn, &call_parameters)?;
+
+            let mut statement = conn.prepare(
+                r#" INSERT INTO mailbox (init_peer_id, message, topic) VALUES (?, ?, ?)"#,
+            )?;
+            statement.bind(1, init_peer_id)?;
+            statement.bind(2, message.as_str())?;
+            statement.bind(3, topic.as_str())?;
+            statement.next()?;
+
+            Ok(())
+        })?
     };
 
     match result {
@@ -46,12 +98,20 @@ pub fn push_mailbox(message: String) -> UnitValue {
 }
 
 #[marine]
-/// Get all messages from the mailbox in FIFO order.
+/// Get all messages from the mailbox, the latest first. Use `mailbox_peek` to get them in FIFO order.
 pub fn get_mailbox() -> GetMailboxResult {
+    get_mailbox_topic(DEFAULT_TOPIC.to_string())
+}
+
+#[marine]
+/// Get all messages of the topic, the latest first.
+pub fn get_mailbox_topic(topic: String) -> GetMailboxResult {
     let result: eyre::Result<Vec<MailboxMessage>> = try {
         let conn = db();
-        let statement = conn
-            .prepare(r#"SELECT init_peer_id, timestamp, message FROM mailbox ORDER BY id DESC"#)?;
+        let mut statement = conn.prepare(f!(
+            "SELECT {MAILBOX_COLUMNS} FROM mailbox WHERE topic = ? ORDER BY id DESC"
+        ))?;
+        statement.bind(1, topic.as_str())?;
         let messages: Vec<MailboxMessage> = fetch_rows(statement, |statement| {
             Ok(Some(MailboxMessage::read(statement)?))
         });
@@ -64,8 +124,17 @@ pub fn get_mailbox() -> GetMailboxResult {
 
 #[marine]
 /// Get the latest mailbox message and remove it from the mailbox.
-/// result.absent is true if there are no messages in the mailbox.
+/// Messages received with `mailbox_receive` and not acknowledged yet are skipped.
+/// result.absent is true if there are no such messages in the mailbox.
 pub fn pop_mailbox() -> PopMailboxResult {
+    pop_mailbox_topic(DEFAULT_TOPIC.to_string())
+}
+
+#[marine]
+/// Get the latest message of the topic and remove it from the mailbox.
+/// Messages received with `mailbox_receive` and not acknowledged yet are skipped.
+/// result.absent is true if there are no such messages in the topic.
+pub fn pop_mailbox_topic(topic: String) -> PopMailboxResult {
     let call_parameters = marine_rs_sdk::get_call_parameters();
 
     // We want to prevent anyone except this spell to pop from mailbox
@@ -76,28 +145,268 @@ pub fn pop_mailbox() -> PopMailboxResult {
         .into();
     }
 
-    let db = db();
     let result: eyre::Result<Option<MailboxMessage>> = try {
-        let mut get = db.prepare(
-            r#" SELECT init_peer_id, timestamp, message, id FROM mailbox ORDER BY id DESC LIMIT 1"#,
-        )?;
-
-        let mut message = None;
-        if let State::Row = get.next()? {
-            message = Some(MailboxMessage::read(&mut get)?);
-
-            let id = get.read::<i64>(3)?;
-            let mut delete = db.prepare(r#"DELETE FROM mailbox WHERE id = ?"#)?;
-            delete.bind(1, id)?;
-            delete.next()?;
+        let conn = db();
+        in_transaction(&conn, |conn| {
+            let mut get = conn.prepare(f!(
+                "SELECT {MAILBOX_COLUMNS} FROM mailbox WHERE topic = ? AND {IS_VISIBLE} ORDER BY id DESC LIMIT 1"
+            ))?;
+            get.bind(1, topic.as_str())?;
+
+            let mut message = None;
+            if let State::Row = get.next()? {
+                let popped = MailboxMessage::read(&mut get)?;
+                delete_message(conn, popped.id)?;
+                message = Some(popped);
+            }
+
+            Ok(message)
+        })?
+    };
+
+    result.into()
+}
+
+#[marine]
+/// Get the oldest mailbox message and remove it from the mailbox.
+/// Messages received with `mailbox_receive` and not acknowledged yet are skipped
+/// until they are visible again.
+/// result.absent is true if there are no such messages in the mailbox.
+pub fn mailbox_dequeue() -> PopMailboxResult {
+    mailbox_dequeue_topic(DEFAULT_TOPIC.to_string())
+}
+
+#[marine]
+/// Get the oldest message of the topic and remove it from the mailbox, see `mailbox_dequeue`.
+pub fn mailbox_dequeue_topic(topic: String) -> PopMailboxResult {
+    let call_parameters = marine_rs_sdk::get_call_parameters();
+
+    if !is_by_spell(&call_parameters) {
+        return Err(eyre::eyre!(
+            "mailbox_dequeue can be called only by the associated spell script"
+        ))
+        .into();
+    }
+
+    let result: eyre::Result<Option<MailboxMessage>> = try {
+        let conn = db();
+        in_transaction(&conn, |conn| {
+            let message = oldest_visible(conn, &topic)?;
+            if let Some(message) = &message {
+                delete_message(conn, message.id)?;
+            }
+            Ok(message)
+        })?
+    };
+
+    result.into()
+}
+
+#[marine]
+/// Get up to `n` oldest mailbox messages without removing them, the oldest first.
+/// Messages received with `mailbox_receive` and not acknowledged yet are skipped.
+pub fn mailbox_peek(n: u32) -> GetMailboxResult {
+    mailbox_peek_topic(DEFAULT_TOPIC.to_string(), n)
+}
+
+#[marine]
+/// Get up to `n` oldest messages of the topic without removing them, see `mailbox_peek`.
+pub fn mailbox_peek_topic(topic: String, n: u32) -> GetMailboxResult {
+    let result: eyre::Result<Vec<MailboxMessage>> = try {
+        let conn = db();
+        let mut statement = conn.prepare(f!(
+            "SELECT {MAILBOX_COLUMNS} FROM mailbox WHERE topic = ? AND {IS_VISIBLE} ORDER BY id ASC LIMIT ?"
+        ))?;
+        statement.bind(1, topic.as_str())?;
+        statement.bind(2, n as i64)?;
+        fetch_rows(statement, |statement| {
+            Ok(Some(MailboxMessage::read(statement)?))
+        })
+    };
+
+    result.into()
+}
+
+#[marine]
+/// Get the oldest visible mailbox message and hide it for `visibility_timeout_sec` seconds.
+/// The message is removed by `mailbox_ack`, otherwise it's received again after the timeout.
+/// result.absent is true if there are no visible messages in the mailbox.
+pub fn mailbox_receive(visibility_timeout_sec: u32) -> PopMailboxResult {
+    mailbox_receive_topic(DEFAULT_TOPIC.to_string(), visibility_timeout_sec)
+}
+
+#[marine]
+/// Get the oldest visible message of the topic and hide it, see `mailbox_receive`.
+pub fn mailbox_receive_topic(topic: String, visibility_timeout_sec: u32) -> PopMailboxResult {
+    let call_parameters = marine_rs_sdk::get_call_parameters();
+
+    if !is_by_spell(&call_parameters) {
+        return Err(eyre::eyre!(
+            "mailbox_receive can be called only by the associated spell script"
+        ))
+        .into();
+    }
+
+    let result: eyre::Result<Option<MailboxMessage>> = try {
+        if visibility_timeout_sec == 0 {
+            Err(eyre::eyre!("visibility_timeout_sec must be greater than 0"))?;
         }
 
-        message
+        let conn = db();
+        in_transaction(&conn, |conn| {
+            let message = oldest_visible(conn, &topic)?;
+            if let Some(message) = &message {
+                let mut statement = conn.prepare(
+                    r#"
+                    UPDATE mailbox
+                       SET visible_at = CAST(strftime('%s', 'now') AS INTEGER) + ?
+                     WHERE id = ?
+                    "#,
+                )?;
+                statement.bind(1, visibility_timeout_sec as i64)?;
+                statement.bind(2, message.id as i64)?;
+                statement.next()?;
+            }
+            Ok(message)
+        })?
     };
 
     result.into()
 }
 
+#[marine]
+/// Remove the message received with `mailbox_receive` or `mailbox_receive_topic` from the mailbox.
+/// Only messages in flight can be acknowledged: once the timeout is over, the message is back
+/// in the mailbox and must be received again.
+pub fn mailbox_ack(id: u64) -> UnitValue {
+    let call_parameters = marine_rs_sdk::get_call_parameters();
+
+    if !is_by_spell(&call_parameters) {
+        return UnitValue::error("mailbox_ack can be called only by the associated spell script");
+    }
+
+    let result: eyre::Result<()> = try {
+        let conn = db();
+        let mut statement =
+            conn.prepare(f!("DELETE FROM mailbox WHERE id = ? AND {IS_IN_FLIGHT}"))?;
+        statement.bind(1, id as i64)?;
+        statement.next()?;
+        if conn.changes() == 0 {
+            Err(eyre::eyre!(
+                "message {id} isn't received or its timeout is over"
+            ))?;
+        }
+    };
+
+    match result {
+        Ok(_) => UnitValue::ok(),
+        Err(e) => UnitValue::error(format!("Error acknowledging mailbox message: {}", e)),
+    }
+}
+
+#[marine]
+/// Set how many latest messages the topic keeps, 0 resets the topic to the default `max_mailbox`.
+/// The topic is still a part of the mailbox, so the oldest messages of all topics are deleted
+/// when the mailbox holds more than `max_mailbox_total` messages.
+/// Messages above the new limit are deleted right away, the oldest first.
+pub fn set_mailbox_topic_limit(topic: String, max_messages: u32) -> UnitValue {
+    if !is_by_creator() {
+        return SetLimitsForbidden.into();
+    }
+
+    let result: eyre::Result<()> = try {
+        let conn = db();
+        in_transaction(&conn, |conn| {
+            write_mailbox_limit(conn, &f!("max_mailbox:{topic}"), max_messages)?;
+            trim_topic(conn, &topic)
+        })?
+    };
+
+    result.into()
+}
+
+#[marine]
+/// Set how many latest messages the mailbox keeps in all topics together, 0 removes the limit.
+/// Messages above the new limit are deleted right away, the oldest first.
+pub fn set_mailbox_total_limit(max_messages: u32) -> UnitValue {
+    if !is_by_creator() {
+        return SetLimitsForbidden.into();
+    }
+
+    let result: eyre::Result<()> = try {
+        let conn = db();
+        in_transaction(&conn, |conn| {
+            write_mailbox_limit(conn, "max_mailbox_total", max_messages)?;
+            trim_total(conn)
+        })?
+    };
+
+    result.into()
+}
+
+/// Writes the mailbox limit to `config_table`, 0 deletes it
+fn write_mailbox_limit(conn: &Connection, parameter: &str, max_messages: u32) -> eyre::Result<()> {
+    let mut statement = if max_messages == 0 {
+        conn.prepare("DELETE FROM config_table WHERE parameter = ?")?
+    } else {
+        let mut statement = conn.prepare("INSERT OR REPLACE INTO config_table VALUES (?, ?)")?;
+        statement.bind(2, max_messages as i64)?;
+        statement
+    };
+    statement.bind(1, parameter)?;
+    statement.next()?;
+
+    Ok(())
+}
+
+/// Deletes the oldest messages of the topic above its limit and updates `count_mailbox`
+fn trim_topic(conn: &Connection, topic: &str) -> eyre::Result<()> {
+    let mut statement = conn.prepare(
+        r#"
+        DELETE FROM mailbox
+         WHERE topic = ?1
+           AND id NOT IN (
+               SELECT id FROM mailbox WHERE topic = ?1 ORDER BY timestamp DESC, id DESC
+                LIMIT COALESCE(
+                      (SELECT value FROM config_table WHERE parameter = 'max_mailbox:' || ?1),
+                      (SELECT value FROM config_table WHERE parameter = 'max_mailbox')
+                )
+           )
+        "#,
+    )?;
+    statement.bind(1, topic)?;
+    statement.next()?;
+
+    update_count(conn)
+}
+
+/// Deletes the oldest messages of all topics above `max_mailbox_total` and updates `count_mailbox`
+fn trim_total(conn: &Connection) -> eyre::Result<()> {
+    conn.execute(
+        r#"
+        DELETE FROM mailbox
+         WHERE id NOT IN (
+               SELECT id FROM mailbox ORDER BY timestamp DESC, id DESC
+                -- a negative limit means no limit in sqlite
+                LIMIT COALESCE(
+                      (SELECT value FROM config_table WHERE parameter = 'max_mailbox_total'),
+                      -1
+                )
+           )
+        "#,
+    )?;
+
+    update_count(conn)
+}
+
+fn update_count(conn: &Connection) -> eyre::Result<()> {
+    conn.execute(
+        "UPDATE config_table SET value = (SELECT COUNT(*) FROM mailbox) WHERE parameter = 'count_mailbox'",
+    )?;
+
+    Ok(())
+}
+
 #[test_env_helpers::after_each]
 #[cfg(test)]
 mod tests {
@@ -223,4 +532,253 @@ mod tests {
         assert!(messages.success, "{}", messages.error);
         assert_eq!(messages.messages.len(), 0);
     }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_mailbox_fifo(spell: marine_test_env::spell::ModuleInterface) {
+        let service_id = Uuid::new_v4();
+        let particle_id = format!("spell_{}_0", service_id);
+        let cp = cp(service_id.to_string(), particle_id);
+
+        for i in 0..3 {
+            let store = spell.push_mailbox_cp(format!("message {i}"), cp.clone());
+            assert!(store.success, "{}", store.error);
+        }
+
+        let peek = spell.mailbox_peek(2);
+        assert!(peek.success, "{}", peek.error);
+        let messages: Vec<_> = peek.messages.iter().map(|m| m.message.as_str()).collect();
+        assert_eq!(messages, vec!["message 0", "message 1"]);
+        assert!(peek.messages[0].id < peek.messages[1].id);
+
+        let dequeue = spell.mailbox_dequeue();
+        assert!(!dequeue.success, "only the spell can dequeue");
+
+        for i in 0..3 {
+            let dequeue = spell.mailbox_dequeue_cp(cp.clone());
+            assert!(dequeue.success, "{}", dequeue.error);
+            assert!(!dequeue.absent);
+            assert_eq!(dequeue.message[0].message, format!("message {i}"));
+        }
+        let dequeue = spell.mailbox_dequeue_cp(cp);
+        assert!(dequeue.success, "{}", dequeue.error);
+        assert!(dequeue.absent);
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_mailbox_receive_ack(spell: marine_test_env::spell::ModuleInterface) {
+        let service_id = Uuid::new_v4();
+        let particle_id = format!("spell_{}_0", service_id);
+        let cp = cp(service_id.to_string(), particle_id);
+
+        for i in 0..2 {
+            let store = spell.push_mailbox_cp(format!("message {i}"), cp.clone());
+            assert!(store.success, "{}", store.error);
+        }
+
+        let first = spell.mailbox_receive_cp(1, cp.clone());
+        assert!(first.success, "{}", first.error);
+        let first = &first.message[0];
+        assert_eq!(first.message, "message 0");
+
+        // the received message is hidden until the timeout
+        let second = spell.mailbox_receive_cp(1, cp.clone());
+        assert_eq!(second.message[0].message, "message 1");
+        let none = spell.mailbox_receive_cp(1, cp.clone());
+        assert!(none.success, "{}", none.error);
+        assert!(none.absent);
+        // messages in flight can't be peeked or popped by other consumers
+        let peek = spell.mailbox_peek(10);
+        assert!(peek.success, "{}", peek.error);
+        assert!(peek.messages.is_empty());
+        let pop = spell.pop_mailbox_cp(cp.clone());
+        assert!(pop.success, "{}", pop.error);
+        assert!(pop.absent);
+
+        let ack = spell.mailbox_ack_cp(second.message[0].id, cp.clone());
+        assert!(ack.success, "{}", ack.error);
+        let ack = spell.mailbox_ack_cp(second.message[0].id, cp.clone());
+        assert!(!ack.success, "a message can be acknowledged only once");
+
+        // the unacknowledged message comes back after the timeout
+        std::thread::sleep(std::time::Duration::from_secs(2));
+        let ack = spell.mailbox_ack_cp(first.id, cp.clone());
+        assert!(!ack.success, "a timed out message must be received again");
+        let again = spell.mailbox_receive_cp(1, cp.clone());
+        assert!(again.success, "{}", again.error);
+        assert_eq!(again.message[0].id, first.id);
+
+        let ack = spell.mailbox_ack_cp(first.id, cp);
+        assert!(ack.success, "{}", ack.error);
+        assert!(spell.get_mailbox().messages.is_empty());
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_mailbox_ack_unreceived(spell: marine_test_env::spell::ModuleInterface) {
+        let service_id = Uuid::new_v4();
+        let particle_id = format!("spell_{}_0", service_id);
+        let cp = cp(service_id.to_string(), particle_id);
+
+        let store = spell.push_mailbox_cp("message".into(), cp.clone());
+        assert!(store.success, "{}", store.error);
+        let peek = spell.mailbox_peek(1);
+        assert!(peek.success, "{}", peek.error);
+
+        let ack = spell.mailbox_ack_cp(peek.messages[0].id, cp);
+        assert!(
+            !ack.success,
+            "a message must be received before it's acknowledged"
+        );
+        assert_eq!(spell.get_mailbox().messages.len(), 1);
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_mailbox_topics(spell: marine_test_env::spell::ModuleInterface) {
+        let service_id = Uuid::new_v4();
+        let particle_id = format!("spell_{}_0", service_id);
+        let cp = cp(service_id.to_string(), particle_id);
+
+        let store = spell.push_mailbox_cp("default".into(), cp.clone());
+        assert!(store.success, "{}", store.error);
+        for i in 0..3 {
+            let store =
+                spell.push_mailbox_topic_cp("commands".into(), format!("command {i}"), cp.clone());
+            assert!(store.success, "{}", store.error);
+        }
+        let store = spell.push_mailbox_topic_cp("status".into(), "ping".into(), cp.clone());
+        assert!(store.success, "{}", store.error);
+
+        let default: Vec<_> = spell.get_mailbox().messages;
+        assert_eq!(default.len(), 1);
+        assert_eq!(default[0].message, "default");
+        let commands = spell.get_mailbox_topic("commands".into());
+        assert!(commands.success, "{}", commands.error);
+        let commands: Vec<_> = commands
+            .messages
+            .iter()
+            .map(|m| m.message.clone())
+            .collect();
+        assert_eq!(commands, vec!["command 2", "command 1", "command 0"]);
+
+        let pop = spell.pop_mailbox_topic_cp("status".into(), cp.clone());
+        assert!(pop.success, "{}", pop.error);
+        assert_eq!(pop.message[0].message, "ping");
+        let pop = spell.pop_mailbox_topic_cp("status".into(), cp.clone());
+        assert!(pop.absent);
+        assert_eq!(spell.get_mailbox().messages.len(), 1);
+
+        // a topic can be limited below `max_mailbox`
+        let set = spell.set_mailbox_topic_limit_cp("commands".into(), 2, cp.clone());
+        assert!(set.success, "{}", set.error);
+        let commands = spell.get_mailbox_topic("commands".into()).messages;
+        assert_eq!(commands.len(), 2);
+        let store = spell.push_mailbox_topic_cp("commands".into(), "command 3".into(), cp.clone());
+        assert!(store.success, "{}", store.error);
+        let commands: Vec<_> = spell
+            .get_mailbox_topic("commands".into())
+            .messages
+            .iter()
+            .map(|m| m.message.clone())
+            .collect();
+        assert_eq!(commands, vec!["command 3", "command 2"]);
+        assert_eq!(spell.get_mailbox().messages.len(), 1);
+
+        let mut other = cp;
+        other.particle.init_peer_id = "other".to_string();
+        let set = spell.set_mailbox_topic_limit_cp("commands".into(), 10, other);
+        assert!(!set.success, "only the creator can set the topic limits");
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_mailbox_total_limit(spell: marine_test_env::spell::ModuleInterface) {
+        use marine_test_env::spell::Limits;
+
+        let service_id = Uuid::new_v4();
+        let particle_id = format!("spell_{}_0", service_id);
+        let cp = cp(service_id.to_string(), particle_id);
+        let limits = Limits {
+            max_logs: 10,
+            max_mailbox: 3,
+            max_particles: 10,
+            max_runs: 10,
+            max_error_age_sec: 0,
+        };
+        let set = spell.set_limits_cp(limits, cp.clone());
+        assert!(set.success, "set_limits failed: {}", set.error);
+
+        // a flood on one topic doesn't evict the messages of other topics
+        let store = spell.push_mailbox_topic_cp("quiet".into(), "hello".into(), cp.clone());
+        assert!(store.success, "{}", store.error);
+        for i in 0..10 {
+            let store =
+                spell.push_mailbox_topic_cp("noisy".into(), format!("spam {i}"), cp.clone());
+            assert!(store.success, "{}", store.error);
+        }
+        assert_eq!(spell.get_mailbox_topic("quiet".into()).messages.len(), 1);
+        assert_eq!(spell.get_mailbox_topic("noisy".into()).messages.len(), 3);
+
+        // the whole mailbox is capped separately, the oldest messages of all topics go first
+        let set = spell.set_mailbox_total_limit_cp(3, cp.clone());
+        assert!(set.success, "{}", set.error);
+        assert!(spell.get_mailbox_topic("quiet".into()).messages.is_empty());
+        for i in 0..10 {
+            let store =
+                spell.push_mailbox_topic_cp(format!("topic {i}"), "spam".into(), cp.clone());
+            assert!(store.success, "{}", store.error);
+        }
+        let stats = spell.get_storage_stats(0).stats;
+        let mailbox = stats.tables.iter().find(|t| t.table == "mailbox").unwrap();
+        assert_eq!(mailbox.rows, 3);
+        assert!(!mailbox.counter_drift, "mailbox counter must be in sync");
+        assert!(spell
+            .get_mailbox_topic("topic 0".into())
+            .messages
+            .is_empty());
+        assert_eq!(spell.get_mailbox_topic("topic 9".into()).messages.len(), 1);
+
+        let set = spell.set_mailbox_total_limit_cp(0, cp.clone());
+        assert!(set.success, "{}", set.error);
+        let store = spell.push_mailbox_topic_cp("quiet".into(), "hello".into(), cp.clone());
+        assert!(store.success, "{}", store.error);
+        let stats = spell.get_storage_stats(0).stats;
+        let mailbox = stats.tables.iter().find(|t| t.table == "mailbox").unwrap();
+        assert_eq!(mailbox.rows, 4);
+
+        let mut other = cp;
+        other.particle.init_peer_id = "other".to_string();
+        let set = spell.set_mailbox_total_limit_cp(10, other);
+        assert!(!set.success, "only the creator can set the mailbox limit");
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_mailbox_topic_queue(spell: marine_test_env::spell::ModuleInterface) {
+        let service_id = Uuid::new_v4();
+        let particle_id = format!("spell_{}_0", service_id);
+        let cp = cp(service_id.to_string(), particle_id);
+
+        let store = spell.push_mailbox_cp("default".into(), cp.clone());
+        assert!(store.success, "{}", store.error);
+        for i in 0..3 {
+            let store = spell.push_mailbox_topic_cp("jobs".into(), format!("job {i}"), cp.clone());
+            assert!(store.success, "{}", store.error);
+        }
+
+        let received = spell.mailbox_receive_topic_cp("jobs".into(), 60, cp.clone());
+        assert!(received.success, "{}", received.error);
+        assert_eq!(received.message[0].message, "job 0");
+        let peek = spell.mailbox_peek_topic("jobs".into(), 10);
+        assert!(peek.success, "{}", peek.error);
+        let peek: Vec<_> = peek.messages.iter().map(|m| m.message.clone()).collect();
+        assert_eq!(peek, vec!["job 1", "job 2"]);
+
+        let dequeue = spell.mailbox_dequeue_topic_cp("jobs".into(), cp.clone());
+        assert!(dequeue.success, "{}", dequeue.error);
+        assert_eq!(dequeue.message[0].message, "job 1");
+        let ack = spell.mailbox_ack_cp(received.message[0].id, cp.clone());
+        assert!(ack.success, "{}", ack.error);
+
+        // the default topic isn't touched
+        let dequeue = spell.mailbox_dequeue_cp(cp);
+        assert_eq!(dequeue.message[0].message, "default");
+        assert_eq!(spell.get_mailbox_topic("jobs".into()).messages.len(), 1);
+    }
 }
@@ -0,0 +1,318 @@
+/*
+ * Aqua Spell Service
+ *
+ * Copyright (C) 2024 Fluence DAO
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation version 3 of the
+ * License.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use marine_rs_sdk::{marine, CallParameters};
+use marine_sqlite_connector::{Connection, State};
+
+use fluence_spell_dtos::error::SpellError::{
+    MailboxRateLimited, MailboxSenderForbidden, SetMailboxPolicyForbidden,
+};
+use fluence_spell_dtos::mailbox::{MailboxPolicy, MailboxPolicyValue};
+use fluence_spell_dtos::value::UnitValue;
+
+use crate::auth::{is_by_creator, is_by_trusted_peer};
+use crate::misc::{fetch_rows, in_transaction, read_config, write_config};
+use crate::schema::db;
+
+/// Sender policy modes, `mailbox_policy` in `config_table` is the index of the mode
+const POLICY_MODES: [&str; 3] = ["open", "allowlist", "trusted"];
+const NOW: &str = "CAST(strftime('%s', 'now') AS INTEGER)";
+
+fn read_mode(conn: &Connection) -> eyre::Result<&'static str> {
+    let mode = read_config(conn, "mailbox_policy")? as usize;
+    POLICY_MODES
+        .get(mode)
+        .copied()
+        .ok_or_else(|| eyre::eyre!("unknown mailbox sender policy {mode}"))
+}
+
+fn is_allowlisted(conn: &Connection, peer_id: &str) -> eyre::Result<bool> {
+    let mut statement =
+        conn.prepare("SELECT EXISTS (SELECT 1 FROM mailbox_allowlist WHERE peer_id = ?)")?;
+    statement.bind(1, peer_id)?;
+    statement.next()?;
+
+    Ok(statement.read::<i64>(0)? != 0)
+}
+
+/// Counts the message in the current rate limit window of the peer.
+/// Windows are fixed, the first message after a window is over starts a new one.
+fn count_message(
+    conn: &Connection,
+    peer_id: &str,
+    limit: u32,
+    window_sec: u32,
+) -> eyre::Result<()> {
+    // forget the peers whose windows are over, so strangers don't pile up
+    conn.execute(f!(
+        "DELETE FROM mailbox_senders WHERE window_start <= {NOW} - {window_sec}"
+    ))?;
+
+    let mut statement = conn.prepare("SELECT count FROM mailbox_senders WHERE peer_id = ?")?;
+    statement.bind(1, peer_id)?;
+    let count = if let State::Row = statement.next()? {
+        statement.read::<i64>(0)? as u32
+    } else {
+        0
+    };
+    if count >= limit {
+        Err(MailboxRateLimited(peer_id.to_string(), limit, window_sec))?;
+    }
+
+    let mut statement = if count == 0 {
+        conn.prepare(f!(
+            "INSERT INTO mailbox_senders (peer_id, window_start, count) VALUES (?, {NOW}, 1)"
+        ))?
+    } else {
+        conn.prepare("UPDATE mailbox_senders SET count = count + 1 WHERE peer_id = ?")?
+    };
+    statement.bind(1, peer_id)?;
+    statement.next()?;
+
+    Ok(())
+}
+
+/// Checks that the sender policy lets the caller push a message and counts it for the rate limit.
+pub fn guard_mailbox_push(conn: &Connection, call_parameters: &CallParameters) -> eyre::Result<()> {
+    if is_by_trusted_peer(call_parameters) {
+        return Ok(());
+    }
+
+    let peer_id = &call_parameters.particle.init_peer_id;
+    let mode = read_mode(conn)?;
+    let allowed = match mode {
+        "open" => true,
+        "allowlist" => is_allowlisted(conn, peer_id)?,
+        _ => false,
+    };
+    if !allowed {
+        Err(MailboxSenderForbidden(peer_id.clone(), mode.to_string()))?;
+    }
+
+    let limit = read_config(conn, "mailbox_rate_limit")? as u32;
+    if limit > 0 {
+        let window_sec = read_config(conn, "mailbox_rate_window_sec")? as u32;
+        count_message(conn, peer_id, limit, window_sec)?;
+    }
+
+    Ok(())
+}
+
+#[marine]
+/// Set who can push messages to the mailbox and how often. Only the creator can change the policy.
+/// The allowlist is replaced with the given one.
+pub fn set_mailbox_policy(policy: MailboxPolicy) -> UnitValue {
+    if !is_by_creator() {
+        return SetMailboxPolicyForbidden.into();
+    }
+
+    let result: eyre::Result<()> = try {
+        let mode = POLICY_MODES
+            .iter()
+            .position(|mode| *mode == policy.mode)
+            .ok_or_else(|| {
+                eyre::eyre!(
+                    "unknown mailbox sender policy `{}`, expected one of {POLICY_MODES:?}",
+                    policy.mode
+                )
+            })?;
+        if policy.rate_limit > 0 && policy.rate_window_sec == 0 {
+            Err(eyre::eyre!("`rate_window_sec` must be greater than 0"))?;
+        }
+
+        let conn = db();
+        in_transaction(&conn, |conn| {
+            write_config(conn, "mailbox_policy", mode as u64)?;
+            write_config(conn, "mailbox_rate_limit", policy.rate_limit as u64)?;
+            write_config(
+                conn,
+                "mailbox_rate_window_sec",
+                policy.rate_window_sec as u64,
+            )?;
+
+            conn.execute("DELETE FROM mailbox_allowlist")?;
+            for peer_id in &policy.allowlist {
+                let mut statement =
+                    conn.prepare("INSERT OR IGNORE INTO mailbox_allowlist VALUES (?)")?;
+                statement.bind(1, peer_id.as_str())?;
+                statement.next()?;
+            }
+
+            Ok(())
+        })?
+    };
+
+    result.into()
+}
+
+#[marine]
+pub fn get_mailbox_policy() -> MailboxPolicyValue {
+    let result: eyre::Result<MailboxPolicy> = try {
+        let conn = db();
+        let statement = conn.prepare("SELECT peer_id FROM mailbox_allowlist ORDER BY peer_id")?;
+        MailboxPolicy {
+            mode: read_mode(&conn)?.to_string(),
+            allowlist: fetch_rows(statement, |statement| {
+                Ok(Some(statement.read::<String>(0)?))
+            }),
+            rate_limit: read_config(&conn, "mailbox_rate_limit")? as u32,
+            rate_window_sec: read_config(&conn, "mailbox_rate_window_sec")? as u32,
+        }
+    };
+
+    result.into()
+}
+
+#[test_env_helpers::after_each]
+#[cfg(test)]
+mod tests {
+    use marine_rs_sdk::CallParameters;
+    use marine_rs_sdk::ParticleParameters;
+    use marine_rs_sdk_test::marine_test;
+
+    use crate::schema::DEFAULT_MAILBOX_RATE_WINDOW_SEC;
+
+    const DB_FILE: &str = "./tests_artifacts/spell.sqlite";
+
+    #[ctor::ctor]
+    /// usage of 'ctor' makes this function run only once
+    fn before_all_tests() {
+        std::fs::remove_file(DB_FILE).ok();
+    }
+
+    /// after_each macro copy-pastes this function into every test
+    fn after_each() {
+        std::fs::remove_file(DB_FILE).ok();
+    }
+
+    fn call_params(init_peer_id: &str) -> CallParameters {
+        CallParameters {
+            particle: ParticleParameters {
+                init_peer_id: init_peer_id.to_string(),
+                id: "some-particle".to_string(),
+                ..<_>::default()
+            },
+            service_creator_peer_id: "worker-id".to_string(),
+            service_id: "spell-id".to_string(),
+            worker_id: "worker-id".to_string(),
+            host_id: "host-id".to_string(),
+            tetraplets: vec![],
+        }
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_default_policy(spell: marine_test_env::spell::ModuleInterface) {
+        let policy = spell.get_mailbox_policy();
+        assert!(
+            policy.success,
+            "get_mailbox_policy failed: {}",
+            policy.error
+        );
+        assert_eq!(policy.policy.mode, "open");
+        assert!(policy.policy.allowlist.is_empty());
+        assert_eq!(policy.policy.rate_limit, 0);
+        assert_eq!(
+            policy.policy.rate_window_sec as usize,
+            DEFAULT_MAILBOX_RATE_WINDOW_SEC
+        );
+
+        let push = spell.push_mailbox_cp("hello".into(), call_params("stranger"));
+        assert!(push.success, "push_mailbox failed: {}", push.error);
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_sender_policy(spell: marine_test_env::spell::ModuleInterface) {
+        use marine_test_env::spell::MailboxPolicy;
+
+        let policy = MailboxPolicy {
+            mode: "trusted".to_string(),
+            allowlist: vec![],
+            rate_limit: 0,
+            rate_window_sec: 60,
+        };
+        let set = spell.set_mailbox_policy_cp(policy.clone(), call_params("stranger"));
+        assert!(!set.success, "only the creator can set the policy");
+        let set = spell.set_mailbox_policy_cp(policy, call_params("worker-id"));
+        assert!(set.success, "set_mailbox_policy failed: {}", set.error);
+
+        let push = spell.push_mailbox_cp("hello".into(), call_params("stranger"));
+        assert!(!push.success, "strangers must be rejected");
+        assert!(
+            push.error.contains("`trusted` sender policy"),
+            "{}",
+            push.error
+        );
+        let push = spell.push_mailbox_cp("hello".into(), call_params("host-id"));
+        assert!(push.success, "push_mailbox failed: {}", push.error);
+
+        let policy = MailboxPolicy {
+            mode: "allowlist".to_string(),
+            allowlist: vec!["friend".to_string()],
+            rate_limit: 0,
+            rate_window_sec: 60,
+        };
+        let set = spell.set_mailbox_policy_cp(policy, call_params("worker-id"));
+        assert!(set.success, "set_mailbox_policy failed: {}", set.error);
+        let push = spell.push_mailbox_cp("hello".into(), call_params("friend"));
+        assert!(push.success, "push_mailbox failed: {}", push.error);
+        let push = spell.push_mailbox_topic_cp("t".into(), "hello".into(), call_params("stranger"));
+        assert!(!push.success, "peers out of the allowlist must be rejected");
+        assert_eq!(spell.get_mailbox_policy().policy.allowlist, vec!["friend"]);
+
+        let policy = MailboxPolicy {
+            mode: "closed".to_string(),
+            allowlist: vec![],
+            rate_limit: 0,
+            rate_window_sec: 60,
+        };
+        let set = spell.set_mailbox_policy_cp(policy, call_params("worker-id"));
+        assert!(!set.success, "unknown modes must be rejected");
+        assert_eq!(spell.get_mailbox().messages.len(), 2);
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_rate_limit(spell: marine_test_env::spell::ModuleInterface) {
+        use marine_test_env::spell::MailboxPolicy;
+
+        let policy = MailboxPolicy {
+            mode: "open".to_string(),
+            allowlist: vec![],
+            rate_limit: 2,
+            rate_window_sec: 60,
+        };
+        let set = spell.set_mailbox_policy_cp(policy, call_params("worker-id"));
+        assert!(set.success, "set_mailbox_policy failed: {}", set.error);
+
+        for _ in 0..2 {
+            let push = spell.push_mailbox_cp("hello".into(), call_params("stranger"));
+            assert!(push.success, "push_mailbox failed: {}", push.error);
+        }
+        let push = spell.push_mailbox_cp("hello".into(), call_params("stranger"));
+        assert!(!push.success, "the third message must be rejected");
+        assert!(push.error.contains("rate limit"), "{}", push.error);
+
+        // the limit is per sender, and the worker isn't limited at all
+        let push = spell.push_mailbox_cp("hello".into(), call_params("other"));
+        assert!(push.success, "push_mailbox failed: {}", push.error);
+        for _ in 0..3 {
+            let push = spell.push_mailbox_cp("hello".into(), call_params("worker-id"));
+            assert!(push.success, "push_mailbox failed: {}", push.error);
+        }
+        assert_eq!(spell.get_mailbox().messages.len(), 6);
+    }
+}
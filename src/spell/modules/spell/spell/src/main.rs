@@ -27,11 +27,17 @@ use marine_rs_sdk::module_manifest;
 pub mod auth;
 pub mod error_handling;
 pub mod kv;
+pub mod limits;
 pub mod log;
 pub mod mailbox;
+pub mod mailbox_policy;
 mod misc;
+pub mod quota;
+pub mod run;
 pub mod schema;
 pub mod script;
+pub mod stats;
+pub mod trace;
 pub mod trigger_config;
 
 module_manifest!();
@@ -18,6 +18,7 @@
  */
 
 use eyre::WrapErr;
+use marine_sqlite_connector::Connection;
 use marine_sqlite_connector::State;
 use marine_sqlite_connector::Statement;
 use std::iter::from_fn;
@@ -39,3 +40,68 @@ where
     .filter_map(|r| r.ok())
     .collect()
 }
+
+/// Reads the first column of the first row of the `query`, NULL is read as 0
+pub fn read_u64(conn: &Connection, query: &str) -> eyre::Result<u64> {
+    let mut statement = conn.prepare(query)?;
+    statement.next()?;
+    Ok(statement.read::<Option<i64>>(0)?.unwrap_or_default() as u64)
+}
+
+/// Reads the `parameter` from `config_table`
+pub fn read_config(conn: &Connection, parameter: &str) -> eyre::Result<u64> {
+    let mut statement = conn.prepare("SELECT value FROM config_table WHERE parameter = ?")?;
+    statement.bind(1, parameter)?;
+    if let State::Row = statement.next()? {
+        Ok(statement.read::<i64>(0)? as u64)
+    } else {
+        Err(eyre::eyre!("`{parameter}` is not set"))
+    }
+}
+
+pub fn write_config(conn: &Connection, parameter: &str, value: u64) -> eyre::Result<()> {
+    let mut statement = conn.prepare("UPDATE config_table SET value = ? WHERE parameter = ?")?;
+    statement.bind(1, value as i64)?;
+    statement.bind(2, parameter)?;
+    statement.next()?;
+
+    Ok(())
+}
+
+/// Writes a limit to `config_table`, limits must be greater than 0
+pub fn write_limit(conn: &Connection, parameter: &str, value: u64) -> eyre::Result<()> {
+    if value == 0 {
+        Err(eyre::eyre!("`{parameter}` must be greater than 0"))?;
+    }
+
+    write_config(conn, parameter, value)
+}
+
+/// Splits `limit + 1` fetched rows into a page of `limit` rows and the flag whether there are more.
+pub fn into_page<T>(mut rows: Vec<T>, limit: u32) -> (Vec<T>, bool) {
+    let has_more = rows.len() > limit as usize;
+    rows.truncate(limit as usize);
+    (rows, has_more)
+}
+
+/// Runs `f` in a single SQLite transaction on `conn`.
+/// The transaction is committed if `f` succeeds and rolled back otherwise.
+pub fn in_transaction<T, F>(conn: &Connection, f: F) -> eyre::Result<T>
+where
+    F: FnOnce(&Connection) -> eyre::Result<T>,
+{
+    conn.execute("BEGIN IMMEDIATE")
+        .context("error starting a transaction")?;
+    match f(conn) {
+        Ok(result) => {
+            conn.execute("COMMIT")
+                .context("error committing a transaction")?;
+            Ok(result)
+        }
+        Err(e) => {
+            // the original error is more important than a failed rollback
+            conn.execute("ROLLBACK").ok();
+            Err(e)
+        }
+    }
+}
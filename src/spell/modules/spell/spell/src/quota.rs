@@ -0,0 +1,421 @@
+/*
+ * Aqua Spell Service
+ *
+ * Copyright (C) 2024 Fluence DAO
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation version 3 of the
+ * License.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use marine_rs_sdk::marine;
+use marine_sqlite_connector::{Connection, State};
+
+use fluence_spell_dtos::error::SpellError::{QuotaExceeded, SetQuotasForbidden};
+use fluence_spell_dtos::quota::{QuotaBudget, QuotaBudgetValue, Quotas};
+use fluence_spell_dtos::value::UnitValue;
+
+use crate::auth::is_by_host;
+use crate::misc::{in_transaction, read_config, write_limit};
+use crate::schema::db;
+use crate::stats::{size_of, KV_COLUMNS, KV_MAP_COLUMNS};
+
+fn read_quotas(conn: &Connection) -> eyre::Result<Quotas> {
+    Ok(Quotas {
+        max_keys: read_config(conn, "max_kv_keys")? as u32,
+        max_value_size: read_config(conn, "max_kv_value_size")? as u32,
+        max_total_bytes: read_config(conn, "max_kv_bytes")?,
+    })
+}
+
+/// Number of distinct keys and bytes stored in `kv` and `kv_map`.
+/// Expired values are purged first, so they don't count. The `kv_expire_trigger` purges them
+/// only on inserts, and the counters are kept by the triggers, see `add_kv_usage_counters`.
+fn usage(conn: &Connection) -> eyre::Result<(u64, u64)> {
+    conn.execute("DELETE FROM kv WHERE expires_at <= strftime('%s', 'now')")?;
+    Ok((
+        read_config(conn, "kv_keys")?,
+        read_config(conn, "kv_bytes")?,
+    ))
+}
+
+fn key_exists(conn: &Connection, key: &str) -> eyre::Result<bool> {
+    let mut statement = conn.prepare(
+        "SELECT EXISTS (SELECT 1 FROM kv WHERE key = ?) OR EXISTS (SELECT 1 FROM kv_map WHERE key = ?)",
+    )?;
+    statement.bind(1, key)?;
+    statement.bind(2, key)?;
+    statement.next()?;
+
+    Ok(statement.read::<i64>(0)? != 0)
+}
+
+/// The part of the key a write goes to, the value stored there is replaced
+pub enum KvSlot<'a> {
+    /// The plain value of the key
+    Value,
+    /// The field of the map
+    Field(&'a str),
+    /// The list element with the `list_order`
+    ListElement(i64),
+}
+
+/// Size of the value the write to the `slot` of the `key` replaces
+fn replaced_size(conn: &Connection, key: &str, slot: &KvSlot) -> eyre::Result<u64> {
+    let mut statement = match slot {
+        KvSlot::Value => conn.prepare(f!(
+            "SELECT {} FROM kv WHERE key = ? AND list_order = -1",
+            size_of(KV_COLUMNS)
+        ))?,
+        KvSlot::Field(field) => {
+            let mut statement = conn.prepare(f!(
+                "SELECT {} FROM kv_map WHERE key = ? AND field = ?",
+                size_of(KV_MAP_COLUMNS)
+            ))?;
+            statement.bind(2, *field)?;
+            statement
+        }
+        KvSlot::ListElement(list_order) => {
+            let mut statement = conn.prepare(f!(
+                "SELECT {} FROM kv WHERE key = ? AND list_order = ?",
+                size_of(KV_COLUMNS)
+            ))?;
+            statement.bind(2, *list_order)?;
+            statement
+        }
+    };
+    statement.bind(1, key)?;
+
+    if let State::Row = statement.next()? {
+        Ok(statement.read::<i64>(0)? as u64)
+    } else {
+        Ok(0)
+    }
+}
+
+/// Checks that writing `value_size` bytes to the `slot` of the `key` fits the quotas.
+/// The bytes of the value being replaced are counted as freed.
+pub fn guard_kv_quota(
+    conn: &Connection,
+    key: &str,
+    slot: KvSlot,
+    value_size: usize,
+) -> eyre::Result<()> {
+    let quotas = read_quotas(conn)?;
+    if value_size > quotas.max_value_size as usize {
+        Err(QuotaExceeded(f!(
+            "the value for `{key}` takes {value_size} bytes, the maximum is {quotas.max_value_size}"
+        )))?;
+    }
+
+    let (keys, bytes) = usage(conn)?;
+    if keys >= quotas.max_keys as u64 && !key_exists(conn, key)? {
+        Err(QuotaExceeded(f!(
+            "can't add the `{key}`, the spell already has {keys} keys of {quotas.max_keys}"
+        )))?;
+    }
+    let bytes = bytes.saturating_sub(replaced_size(conn, key, &slot)?);
+    if bytes + (key.len() + value_size) as u64 > quotas.max_total_bytes {
+        Err(QuotaExceeded(f!(
+            "can't write {value_size} bytes to the `{key}`, {bytes} of {quotas.max_total_bytes} bytes are used"
+        )))?;
+    }
+
+    Ok(())
+}
+
+/// Checks the totals after a write of several keys, which `guard_kv_quota` checks one by one.
+/// Must be called inside the transaction of the write, so the write is rolled back on failure.
+pub fn check_kv_usage(conn: &Connection) -> eyre::Result<()> {
+    let quotas = read_quotas(conn)?;
+    let (keys, bytes) = usage(conn)?;
+    if keys > quotas.max_keys as u64 {
+        Err(QuotaExceeded(f!(
+            "the write needs {keys} keys, the maximum is {quotas.max_keys}"
+        )))?;
+    }
+    if bytes > quotas.max_total_bytes {
+        Err(QuotaExceeded(f!(
+            "the write needs {bytes} bytes, the maximum is {quotas.max_total_bytes}"
+        )))?;
+    }
+
+    Ok(())
+}
+
+#[marine]
+/// Set how much data the spell can keep in its KV. Only the host can change the quotas.
+/// Data above the new quotas isn't deleted, but no more data can be written until it's removed.
+pub fn set_kv_quotas(quotas: Quotas) -> UnitValue {
+    if !is_by_host() {
+        return SetQuotasForbidden.into();
+    }
+
+    let result: eyre::Result<()> = try {
+        let conn = db();
+        in_transaction(&conn, |conn| {
+            write_limit(conn, "max_kv_keys", quotas.max_keys as u64)?;
+            write_limit(conn, "max_kv_value_size", quotas.max_value_size as u64)?;
+            write_limit(conn, "max_kv_bytes", quotas.max_total_bytes)
+        })?
+    };
+
+    result.into()
+}
+
+#[marine]
+/// Get the KV quotas and how many keys and bytes the spell can still write
+pub fn get_kv_budget() -> QuotaBudgetValue {
+    let result: eyre::Result<QuotaBudget> = try {
+        let conn = db();
+        let quotas = read_quotas(&conn)?;
+        let (keys, bytes) = usage(&conn)?;
+        QuotaBudget {
+            keys_left: (quotas.max_keys as u64).saturating_sub(keys) as u32,
+            bytes_left: quotas.max_total_bytes.saturating_sub(bytes),
+            quotas,
+        }
+    };
+
+    result.into()
+}
+
+#[test_env_helpers::after_each]
+#[cfg(test)]
+mod tests {
+    use marine_rs_sdk::CallParameters;
+    use marine_rs_sdk::ParticleParameters;
+    use marine_rs_sdk_test::marine_test;
+
+    use crate::schema::{DEFAULT_MAX_KV_BYTES, DEFAULT_MAX_KV_KEYS, DEFAULT_MAX_KV_VALUE_SIZE};
+
+    const DB_FILE: &str = "./tests_artifacts/spell.sqlite";
+
+    #[ctor::ctor]
+    /// usage of 'ctor' makes this function run only once
+    fn before_all_tests() {
+        std::fs::remove_file(DB_FILE).ok();
+    }
+
+    /// after_each macro copy-pastes this function into every test
+    fn after_each() {
+        std::fs::remove_file(DB_FILE).ok();
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_default_budget(spell: marine_test_env::spell::ModuleInterface) {
+        let budget = spell.get_kv_budget();
+        assert!(budget.success, "get_kv_budget failed: {}", budget.error);
+        let budget = budget.budget;
+        assert_eq!(budget.quotas.max_keys as usize, DEFAULT_MAX_KV_KEYS);
+        assert_eq!(
+            budget.quotas.max_value_size as usize,
+            DEFAULT_MAX_KV_VALUE_SIZE
+        );
+        assert_eq!(budget.quotas.max_total_bytes as usize, DEFAULT_MAX_KV_BYTES);
+        assert_eq!(budget.keys_left as usize, DEFAULT_MAX_KV_KEYS);
+
+        let set = spell.set_string_cp("key".into(), "value".into(), spell_call_params());
+        assert!(set.success, "set_string failed: {}", set.error);
+        let budget = spell.get_kv_budget().budget;
+        assert_eq!(budget.keys_left as usize, DEFAULT_MAX_KV_KEYS - 1);
+        assert_eq!(
+            budget.bytes_left as usize,
+            DEFAULT_MAX_KV_BYTES - "key".len() - "value".len()
+        );
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_quotas_enforced(spell: marine_test_env::spell::ModuleInterface) {
+        use marine_test_env::spell::Quotas;
+
+        let quotas = Quotas {
+            max_keys: 2,
+            max_value_size: 10,
+            max_total_bytes: 30,
+        };
+        let set = spell.set_kv_quotas_cp(quotas, host_call_params());
+        assert!(set.success, "set_kv_quotas failed: {}", set.error);
+
+        let cp = || spell_call_params();
+        let set = spell.set_string_cp("a".into(), "x".repeat(11), cp());
+        assert!(!set.success, "too large values must be rejected");
+        assert!(set.error.contains("KV quota exceeded"), "{}", set.error);
+
+        let set = spell.set_string_cp("a".into(), "x".repeat(10), cp());
+        assert!(set.success, "set_string failed: {}", set.error);
+        let push = spell.list_push_string_cp("b".into(), "y".into(), cp());
+        assert!(push.success, "list_push_string failed: {}", push.error);
+
+        let set = spell.set_u32_cp("c".into(), 1, cp());
+        assert!(!set.success, "the third key must be rejected");
+        let map = spell.map_set_cp("c".into(), "field".into(), "v".into(), cp());
+        assert!(!map.success, "the third key must be rejected for maps too");
+
+        // existing keys can still grow until the total is reached
+        for _ in 0..10 {
+            let _ = spell.list_push_string_cp("b".into(), "y".repeat(5), cp());
+        }
+        let budget = spell.get_kv_budget().budget;
+        assert_eq!(budget.keys_left, 0);
+        assert!(budget.bytes_left < 6, "bytes left: {}", budget.bytes_left);
+        assert!(spell.list_get_strings("b".into()).value.len() < 10);
+
+        // removing data frees the budget
+        let remove = spell.remove_key_cp("b".into(), cp());
+        assert!(remove.success, "remove_key failed: {}", remove.error);
+        let set = spell.set_u32_cp("c".into(), 1, cp());
+        assert!(set.success, "set_u32 failed: {}", set.error);
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_overwrite_near_quota(spell: marine_test_env::spell::ModuleInterface) {
+        use marine_test_env::spell::Quotas;
+
+        let quotas = Quotas {
+            max_keys: 10,
+            max_value_size: 100,
+            max_total_bytes: 20,
+        };
+        let set = spell.set_kv_quotas_cp(quotas, host_call_params());
+        assert!(set.success, "set_kv_quotas failed: {}", set.error);
+
+        let cp = || spell_call_params();
+        let set = spell.set_string_cp("a".into(), "x".repeat(9), cp());
+        assert!(set.success, "set_string failed: {}", set.error);
+        let map = spell.map_set_cp("m".into(), "f".into(), "y".repeat(8), cp());
+        assert!(map.success, "map_set failed: {}", map.error);
+        assert_eq!(spell.get_kv_budget().budget.bytes_left, 0);
+
+        // the replaced values are freed, so smaller values can overwrite them
+        let set = spell.set_string_cp("a".into(), "x".repeat(5), cp());
+        assert!(set.success, "set_string failed: {}", set.error);
+        let map = spell.map_set_cp("m".into(), "f".into(), "y".repeat(3), cp());
+        assert!(map.success, "map_set failed: {}", map.error);
+        let budget = spell.get_kv_budget().budget;
+        assert_eq!(budget.bytes_left, 9);
+        assert_eq!(budget.keys_left, 8);
+
+        let remove = spell.remove_key_cp("m".into(), cp());
+        assert!(remove.success, "remove_key failed: {}", remove.error);
+        let budget = spell.get_kv_budget().budget;
+        assert_eq!(budget.bytes_left, 14);
+        assert_eq!(budget.keys_left, 9);
+
+        // the same for the list elements set in place
+        let push = spell.list_push_string_cp("l".into(), "z".repeat(13), cp());
+        assert!(push.success, "list_push_string failed: {}", push.error);
+        assert_eq!(spell.get_kv_budget().budget.bytes_left, 0);
+        let set = spell.list_set_at_cp("l".into(), 0, "z".repeat(5), cp());
+        assert!(set.success, "list_set_at failed: {}", set.error);
+        assert_eq!(spell.get_kv_budget().budget.bytes_left, 8);
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_expired_frees_quota(spell: marine_test_env::spell::ModuleInterface) {
+        use marine_test_env::spell::Quotas;
+
+        let quotas = Quotas {
+            max_keys: 1,
+            max_value_size: 100,
+            max_total_bytes: 1000,
+        };
+        let set = spell.set_kv_quotas_cp(quotas, host_call_params());
+        assert!(set.success, "set_kv_quotas failed: {}", set.error);
+
+        let cp = || spell_call_params();
+        let set = spell.set_string_with_ttl_cp("a".into(), "x".into(), 1, cp());
+        assert!(set.success, "set_string_with_ttl failed: {}", set.error);
+        let set = spell.set_u32_cp("b".into(), 1, cp());
+        assert!(!set.success, "the second key must be rejected");
+
+        std::thread::sleep(std::time::Duration::from_secs(2));
+
+        let budget = spell.get_kv_budget().budget;
+        assert_eq!(budget.keys_left, 1, "the expired key must not count");
+        assert_eq!(budget.bytes_left, 1000);
+        let set = spell.set_u32_cp("b".into(), 1, cp());
+        assert!(set.success, "set_u32 failed: {}", set.error);
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_quotas_batch(spell: marine_test_env::spell::ModuleInterface) {
+        use marine_test_env::spell::Quotas;
+
+        let quotas = Quotas {
+            max_keys: 2,
+            max_value_size: 100,
+            max_total_bytes: 1000,
+        };
+        let set = spell.set_kv_quotas_cp(quotas, host_call_params());
+        assert!(set.success, "set_kv_quotas failed: {}", set.error);
+
+        // every field fits alone, but not all of them together
+        let set =
+            spell.set_json_fields_cp(r#"{"a": 1, "b": 2, "c": 3}"#.into(), spell_call_params());
+        assert!(!set.success, "the fields must not fit the quota");
+        assert!(!spell.exists("a".into()).value, "nothing must be written");
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_set_quotas_forbidden(spell: marine_test_env::spell::ModuleInterface) {
+        use marine_test_env::spell::Quotas;
+
+        let quotas = Quotas {
+            max_keys: 1,
+            max_value_size: 1,
+            max_total_bytes: 1,
+        };
+        let set = spell.set_kv_quotas_cp(quotas, spell_call_params());
+        assert!(!set.success, "the spell must not set its own quotas");
+        let zero = Quotas {
+            max_keys: 0,
+            max_value_size: 1,
+            max_total_bytes: 1,
+        };
+        let set = spell.set_kv_quotas_cp(zero, host_call_params());
+        assert!(!set.success, "zero quotas must be rejected");
+
+        let budget = spell.get_kv_budget().budget;
+        assert_eq!(budget.quotas.max_keys as usize, DEFAULT_MAX_KV_KEYS);
+    }
+
+    fn spell_call_params() -> CallParameters {
+        CallParameters {
+            particle: ParticleParameters {
+                init_peer_id: "worker-id".to_string(),
+                id: "spell_spell-id_0".to_string(),
+                ..<_>::default()
+            },
+            service_creator_peer_id: "worker-id".to_string(),
+            service_id: "spell-id".to_string(),
+            worker_id: "worker-id".to_string(),
+            host_id: "host-id".to_string(),
+            tetraplets: vec![],
+        }
+    }
+
+    fn host_call_params() -> CallParameters {
+        CallParameters {
+            particle: ParticleParameters {
+                init_peer_id: "host-id".to_string(),
+                id: "some-particle".to_string(),
+                ..<_>::default()
+            },
+            service_creator_peer_id: "worker-id".to_string(),
+            service_id: "spell-id".to_string(),
+            worker_id: "worker-id".to_string(),
+            host_id: "host-id".to_string(),
+            tetraplets: vec![],
+        }
+    }
+}
@@ -0,0 +1,257 @@
+/*
+ * Aqua Spell Service
+ *
+ * Copyright (C) 2024 Fluence DAO
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation version 3 of the
+ * License.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use marine_rs_sdk::marine;
+use marine_sqlite_connector::Statement;
+
+use fluence_spell_dtos::run::{Run, RunHistory, RunStats, RunStatsValue};
+use fluence_spell_dtos::value::UnitValue;
+
+use crate::auth::is_by_spell;
+use crate::misc::{fetch_rows, read_config, read_u64};
+use crate::schema::db;
+
+/// Errors are joined by the particle id, they are kept only for the latest `max_particles` particles
+const HAS_ERRORS: &str =
+    "EXISTS (SELECT 1 FROM errors WHERE errors.particle_id = runs.particle_id)";
+
+/// Parses the counter from the `spell_<spell_id>_<counter>` particle id
+fn parse_counter(particle_id: &str) -> eyre::Result<u64> {
+    particle_id
+        .rsplit('_')
+        .next()
+        .and_then(|counter| counter.parse::<u64>().ok())
+        .ok_or_else(|| eyre::eyre!("particle id '{particle_id}' has no run counter"))
+}
+
+fn read_run(statement: &mut Statement) -> eyre::Result<Option<Run>> {
+    Ok(Some(Run {
+        particle_id: statement.read::<String>(0)?,
+        counter: statement.read::<i64>(1)? as u64,
+        particle_timestamp: statement.read::<i64>(2)? as u64,
+        timestamp: statement.read::<i64>(3)? as u64,
+        has_errors: statement.read::<i64>(4)? != 0,
+    }))
+}
+
+#[marine]
+/// Record that the spell script runs in the current particle. Recording the same particle again does nothing.
+/// It keeps `max_runs` latest runs, see `set_limits`.
+pub fn record_run() -> UnitValue {
+    let call_parameters = marine_rs_sdk::get_call_parameters();
+
+    if !is_by_spell(&call_parameters) {
+        return UnitValue::error("record_run can be called only by the associated spell script");
+    }
+
+    let particle = call_parameters.particle;
+    let result: eyre::Result<()> = try {
+        let counter = parse_counter(&particle.id)?;
+        let conn = db();
+        let mut statement = conn.prepare(
+            r#"
+            INSERT OR IGNORE INTO runs (particle_id, counter, particle_timestamp)
+            VALUES (?, ?, ?)
+            "#,
+        )?;
+        statement.bind(1, particle.id.as_str())?;
+        statement.bind(2, counter as i64)?;
+        statement.bind(3, particle.timestamp as i64)?;
+        statement.next()?;
+    };
+
+    match result {
+        Ok(_) => UnitValue::ok(),
+        Err(e) => UnitValue::error(format!("record_run error: {}", e)),
+    }
+}
+
+#[marine]
+/// Get `limit` latest runs, the latest first
+pub fn get_run_history(limit: u32) -> RunHistory {
+    let result: eyre::Result<Vec<Run>> = try {
+        let conn = db();
+        let mut statement = conn.prepare(f!(r#"
+            SELECT particle_id, counter, particle_timestamp, timestamp, {HAS_ERRORS}
+              FROM runs
+          ORDER BY id DESC
+             LIMIT ?
+            "#))?;
+        statement.bind(1, limit as i64)?;
+        fetch_rows(statement, read_run)
+    };
+
+    result.into()
+}
+
+#[marine]
+/// Get the number of runs, the time of the latest one, how many runs failed and how often the spell runs
+pub fn get_run_stats() -> RunStatsValue {
+    let result: eyre::Result<RunStats> = try {
+        let conn = db();
+        let stored_runs = read_u64(&conn, "SELECT COUNT(*) FROM runs")?;
+        // the subqueries are NULL rather than empty when there are no runs
+        let (first, last) = (
+            read_u64(
+                &conn,
+                "SELECT (SELECT particle_timestamp FROM runs ORDER BY id ASC LIMIT 1)",
+            )?,
+            read_u64(
+                &conn,
+                "SELECT (SELECT particle_timestamp FROM runs ORDER BY id DESC LIMIT 1)",
+            )?,
+        );
+        let average_interval_ms = if stored_runs > 1 {
+            last.saturating_sub(first) / (stored_runs - 1)
+        } else {
+            0
+        };
+
+        RunStats {
+            total_runs: read_config(&conn, "total_runs")?,
+            stored_runs,
+            runs_with_errors: read_u64(&conn, &f!("SELECT COUNT(*) FROM runs WHERE {HAS_ERRORS}"))?,
+            last_run_timestamp: last,
+            average_interval_ms,
+        }
+    };
+
+    result.into()
+}
+
+#[test_env_helpers::after_each]
+#[cfg(test)]
+mod tests {
+    use marine_rs_sdk::ParticleParameters;
+    use marine_rs_sdk_test::marine_test;
+    use uuid::Uuid;
+
+    const DB_FILE: &str = "./tests_artifacts/spell.sqlite";
+
+    #[ctor::ctor]
+    /// usage of 'ctor' makes this function run only once
+    fn before_all_tests() {
+        std::fs::remove_file(DB_FILE).ok();
+    }
+
+    /// after_each macro copy-pastes this function into every test
+    fn after_each() {
+        std::fs::remove_file(DB_FILE).ok();
+    }
+
+    fn cp(service_id: &Uuid, counter: u64, timestamp: u64) -> marine_rs_sdk_test::CallParameters {
+        marine_rs_sdk_test::CallParameters {
+            particle: ParticleParameters {
+                init_peer_id: "folex".to_string(),
+                id: format!("spell_{service_id}_{counter}"),
+                timestamp,
+                ..<_>::default()
+            },
+            service_creator_peer_id: "folex".to_string(),
+            service_id: service_id.to_string(),
+            host_id: "".to_string(),
+            worker_id: "".to_string(),
+            tetraplets: vec![],
+        }
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_run_history(spell: marine_test_env::spell::ModuleInterface) {
+        use marine_test_env::spell::LastError;
+
+        let stats = spell.get_run_stats();
+        assert!(stats.success, "get_run_stats failed: {}", stats.error);
+        assert_eq!(stats.stats.total_runs, 0);
+        assert_eq!(stats.stats.average_interval_ms, 0);
+
+        let service_id = Uuid::new_v4();
+        for counter in 0..3 {
+            let cp = cp(&service_id, counter, 1000 + counter * 500);
+            let record = spell.record_run_cp(cp.clone());
+            assert!(record.success, "record_run failed: {}", record.error);
+            // recording the same particle twice is a no-op
+            let record = spell.record_run_cp(cp);
+            assert!(record.success, "record_run failed: {}", record.error);
+        }
+        let error = LastError {
+            error_code: 1,
+            instruction: "call".to_string(),
+            message: "failed".to_string(),
+            peer_id: "peer".to_string(),
+        };
+        let store = spell.store_error_cp(error, 0, 2000, cp(&service_id, 2, 2000));
+        assert!(store.success, "{}", store.error);
+
+        let history = spell.get_run_history(2);
+        assert!(history.success, "get_run_history failed: {}", history.error);
+        let counters: Vec<_> = history.runs.iter().map(|run| run.counter).collect();
+        assert_eq!(counters, vec![2, 1]);
+        assert!(history.runs[0].has_errors);
+        assert!(!history.runs[1].has_errors);
+        assert_eq!(history.runs[0].particle_timestamp, 2000);
+
+        let stats = spell.get_run_stats().stats;
+        assert_eq!(stats.total_runs, 3);
+        assert_eq!(stats.stored_runs, 3);
+        assert_eq!(stats.runs_with_errors, 1);
+        assert_eq!(stats.last_run_timestamp, 2000);
+        assert_eq!(stats.average_interval_ms, 500);
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_run_retention(spell: marine_test_env::spell::ModuleInterface) {
+        use marine_test_env::spell::Limits;
+
+        let service_id = Uuid::new_v4();
+        let limits = Limits {
+            max_logs: 10,
+            max_mailbox: 10,
+            max_particles: 10,
+            max_runs: 2,
+            max_error_age_sec: 0,
+        };
+        let set = spell.set_limits_cp(limits, cp(&service_id, 0, 0));
+        assert!(set.success, "set_limits failed: {}", set.error);
+
+        for counter in 0..5 {
+            let record = spell.record_run_cp(cp(&service_id, counter, counter * 1000));
+            assert!(record.success, "record_run failed: {}", record.error);
+        }
+
+        let counters: Vec<_> = spell
+            .get_run_history(10)
+            .runs
+            .iter()
+            .map(|run| run.counter)
+            .collect();
+        assert_eq!(counters, vec![4, 3]);
+        let stats = spell.get_run_stats().stats;
+        assert_eq!(stats.total_runs, 5);
+        assert_eq!(stats.stored_runs, 2);
+        assert_eq!(stats.average_interval_ms, 1000);
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_record_run_forbidden(spell: marine_test_env::spell::ModuleInterface) {
+        let mut cp = cp(&Uuid::new_v4(), 0, 0);
+        cp.particle.id = "some-particle".to_string();
+        let record = spell.record_run_cp(cp);
+        assert!(!record.success, "only the spell can record runs");
+        assert!(spell.get_run_history(10).runs.is_empty());
+    }
+}
@@ -17,14 +17,51 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use eyre::WrapErr;
 use fstrings::f;
-use marine_sqlite_connector::Connection;
+use marine_rs_sdk::marine;
+use marine_sqlite_connector::{Connection, State};
+
+use fluence_spell_dtos::value::U32Value;
+
+use crate::misc::{fetch_rows, in_transaction};
+use crate::stats::{size_of, size_of_row, KV_COLUMNS, KV_MAP_COLUMNS};
 
 pub const DEFAULT_MAX_ERR_PARTICLES: usize = 50;
 pub const DEFAULT_MAX_MAILBOX: usize = 50;
+pub const DEFAULT_MAX_MAILBOX_TOTAL: usize = 1000;
+pub const DEFAULT_MAILBOX_RATE_WINDOW_SEC: usize = 60;
 pub const DEFAULT_MAX_LOGS: usize = 500;
+pub const DEFAULT_MAX_RUNS: usize = 500;
+pub const DEFAULT_MAX_KV_KEYS: usize = 10_000;
+pub const DEFAULT_MAX_KV_VALUE_SIZE: usize = 1024 * 1024;
+pub const DEFAULT_MAX_KV_BYTES: usize = 64 * 1024 * 1024;
 pub const DB_FILE: &'static str = "/storage/spell.sqlite";
 
+type Migration = fn(&Connection) -> eyre::Result<()>;
+
+/// Schema migrations in the order they are applied, a database of version N has the first N applied.
+/// The spell state is kept in `/storage` between restarts and updates of the spell,
+/// so never change an existing migration, add a new one instead.
+pub const MIGRATIONS: &[Migration] = &[
+    create_tables,
+    add_typed_kv_columns,
+    add_kv_expiry,
+    create_kv_map,
+    add_kv_value_types,
+    add_log_levels,
+    add_structured_logs,
+    add_particle_tracing,
+    create_runs,
+    add_error_occurrences,
+    add_mailbox_visibility,
+    add_mailbox_topics,
+    create_mailbox_senders,
+    add_kv_usage_counters,
+    cap_mailbox_total,
+    limit_mailbox_topics,
+];
+
 pub fn db() -> Connection {
     // use rand::prelude::*;
     //
@@ -38,140 +75,664 @@ pub fn db() -> Connection {
 
 pub fn create() {
     let conn = db();
+    migrate(&conn).expect("migrate sqlite db");
+    let kv_size = size_of(KV_COLUMNS);
+    let map_size = size_of(KV_MAP_COLUMNS);
+    // The limits and quotas are inserted only once,
+    // so the values set by `set_limits` and `set_kv_quotas` survive restarts.
+    // The counters are recounted in case they went out of sync with the tables.
+    conn.execute(f!(r#"
+        -- maximum number of particles to store information about
+        INSERT OR IGNORE INTO config_table VALUES ('max_particles', {DEFAULT_MAX_ERR_PARTICLES});
+        -- current count of stored particles
+        INSERT OR REPLACE INTO config_table VALUES ('count_particles', (SELECT COUNT(*) FROM particles));
+        -- maximum number of logs to store
+        INSERT OR IGNORE INTO config_table VALUES ('max_logs', {DEFAULT_MAX_LOGS});
+        -- current count of stored logs
+        INSERT OR REPLACE INTO config_table VALUES ('count_logs', (SELECT COUNT(*) FROM logs));
+        -- maximum number of mailbox messages to store
+        INSERT OR IGNORE INTO config_table VALUES ('max_mailbox', {DEFAULT_MAX_MAILBOX});
+        -- maximum number of mailbox messages of all topics together, see `set_mailbox_total_limit`
+        INSERT OR IGNORE INTO config_table VALUES ('max_mailbox_total', {DEFAULT_MAX_MAILBOX_TOTAL});
+        -- current count of stored mailbox messages
+        INSERT OR REPLACE INTO config_table VALUES ('count_mailbox', (SELECT COUNT(*) FROM mailbox));
+        -- maximum number of runs to store
+        INSERT OR IGNORE INTO config_table VALUES ('max_runs', {DEFAULT_MAX_RUNS});
+        -- current count of stored runs
+        INSERT OR REPLACE INTO config_table VALUES ('count_runs', (SELECT COUNT(*) FROM runs));
+        -- number of runs recorded since the spell was installed, it isn't decremented on retention
+        INSERT OR IGNORE INTO config_table VALUES ('total_runs', (SELECT COUNT(*) FROM runs));
+        -- errors older than this number of seconds are deleted, 0 disables the expiry
+        INSERT OR IGNORE INTO config_table VALUES ('max_error_age_sec', 0);
+        -- 1 if the same errors are counted instead of being stored again, see `set_error_deduplication`
+        INSERT OR IGNORE INTO config_table VALUES ('dedup_errors', 0);
+        -- mailbox sender policy, see `set_mailbox_policy`
+        INSERT OR IGNORE INTO config_table VALUES ('mailbox_policy', 0);
+        INSERT OR IGNORE INTO config_table VALUES ('mailbox_rate_limit', 0);
+        INSERT OR IGNORE INTO config_table VALUES ('mailbox_rate_window_sec', {DEFAULT_MAILBOX_RATE_WINDOW_SEC});
+        -- KV quotas, can be changed by the host
+        INSERT OR IGNORE INTO config_table VALUES ('max_kv_keys', {DEFAULT_MAX_KV_KEYS});
+        INSERT OR IGNORE INTO config_table VALUES ('max_kv_value_size', {DEFAULT_MAX_KV_VALUE_SIZE});
+        INSERT OR IGNORE INTO config_table VALUES ('max_kv_bytes', {DEFAULT_MAX_KV_BYTES});
+        -- current number of keys and bytes in the KV, see `add_kv_usage_counters`
+        INSERT OR REPLACE INTO config_table VALUES ('kv_keys', (SELECT COUNT(*) FROM (SELECT key FROM kv UNION SELECT key FROM kv_map)));
+        INSERT OR REPLACE INTO config_table VALUES ('kv_bytes', (SELECT COALESCE(SUM({kv_size}), 0) FROM kv) + (SELECT COALESCE(SUM({map_size}), 0) FROM kv_map));
+        "#))
+        .expect("init sqlite db config");
+}
+
+/// Applies the migrations the database doesn't have yet in a single transaction.
+/// Databases created before the versioning was introduced are considered to be of version 0.
+pub fn migrate(conn: &Connection) -> eyre::Result<()> {
+    conn.execute("CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL)")?;
+    in_transaction(conn, |conn| {
+        let version = read_version(conn)? as usize;
+        if version >= MIGRATIONS.len() {
+            return Ok(());
+        }
+
+        for (idx, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            migration(conn).context(format!("error migrating to version {}", idx + 1))?;
+        }
+
+        conn.execute("DELETE FROM schema_version")?;
+        let mut statement = conn.prepare("INSERT INTO schema_version VALUES (?)")?;
+        statement.bind(1, MIGRATIONS.len() as i64)?;
+        statement.next()?;
+
+        Ok(())
+    })
+}
+
+fn read_version(conn: &Connection) -> eyre::Result<u32> {
+    let mut statement = conn.prepare("SELECT version FROM schema_version")?;
+    if let State::Row = statement.next()? {
+        Ok(statement.read::<i64>(0)? as u32)
+    } else {
+        Ok(0)
+    }
+}
+
+#[marine]
+/// Get the version of the spell database schema, i.e. the number of applied migrations
+pub fn get_schema_version() -> U32Value {
+    let result: eyre::Result<Option<u32>> = try { Some(read_version(&db())?) };
+
+    result.into()
+}
+
+fn add_column(conn: &Connection, table: &str, name: &str, kind: &str) -> eyre::Result<()> {
+    let statement = conn.prepare(f!("SELECT name FROM pragma_table_info('{table}')"))?;
+    let columns: Vec<String> = fetch_rows(statement, |statement| {
+        Ok(Some(statement.read::<String>(0)?))
+    });
+
+    // the column may already be there if the database was created before the versioning
+    if !columns.iter().any(|column| column == name) {
+        conn.execute(f!("ALTER TABLE {table} ADD COLUMN {name} {kind}"))?;
+    }
+
+    Ok(())
+}
+
+/// The layout the spell state had when it moved to `/storage`
+fn create_tables(conn: &Connection) -> eyre::Result<()> {
     conn.execute(
-        f!(r#"
-            CREATE TABLE IF NOT EXISTS trigger_config (
-                -- clock config
-                start_sec INTEGER, end_sec INTEGER, period_sec INTEGER,
-                -- connection pool config
-                connect INTEGER, disconnect INTEGER,
-                -- blockchain config
-                start_block INTEGER, end_block INTEGER
-            );
-
-            -- CREATE TABLE IF NOT EXISTS kv (key TEXT, string TEXT, u32 INTEGER, list_order INTEGER);
-            CREATE TABLE IF NOT EXISTS kv (
-                key TEXT NOT NULL,
-                string TEXT,
-                u32 INTEGER,
-                list_order INTEGER DEFAULT -1,
-
-                PRIMARY KEY(key, list_order)
-            );
-
-            -- particles stored in the database, LRU-like
-            CREATE TABLE IF NOT EXISTS particles (particle_id TEXT PRIMARY KEY, timestamp INTEGER);
-            -- errors happened in particles
-            CREATE TABLE IF NOT EXISTS errors (
-                particle_id TEXT,
-                timestamp INTEGER,
-                error_idx INTEGER,
-                error_code INTEGER,
-                instruction TEXT,
-                message TEXT,
-                peer_id TEXT
-            );
-            CREATE TABLE IF NOT EXISTS config_table (parameter TEXT PRIMARY KEY, value INTEGER NOT NULL);
-            -- maximum number of particles to store information about
-            INSERT OR REPLACE INTO config_table VALUES ('max_particles', {DEFAULT_MAX_ERR_PARTICLES});
-            -- current count of stored particles
-            INSERT OR REPLACE INTO config_table VALUES ('count_particles', 0);
-             -- maximum number of logs to store
-            INSERT OR REPLACE INTO config_table VALUES ('max_logs', {DEFAULT_MAX_LOGS});
-            -- current count of stored logs
-            INSERT OR REPLACE INTO config_table VALUES ('count_logs', 0);
-            -- maximum number of mailbox messages to store
-            INSERT OR REPLACE INTO config_table VALUES ('max_mailbox', {DEFAULT_MAX_MAILBOX});
-            -- current count of stored mailbox messages
-            INSERT OR REPLACE INTO config_table VALUES ('count_mailbox', 0);
-
-            -- if there are more than `max_particles` particles, delete the oldest one
-            CREATE TRIGGER IF NOT EXISTS errors_limit_trigger AFTER INSERT ON particles
-                FOR EACH ROW
-                -- if limit is reached
-                WHEN (SELECT value FROM config_table WHERE parameter = 'count_particles')
-                    > (SELECT value FROM config_table WHERE parameter = 'max_particles')
-                BEGIN
-                    -- delete all errors for the oldest particle
-                    DELETE FROM particles
-                        -- take oldest by 'timestamp' column
-                        WHERE particle_id = (SELECT particle_id FROM particles ORDER BY timestamp LIMIT 1);
-                END;
-
-            -- when a particle is removed, remove its errors
-            CREATE TRIGGER IF NOT EXISTS clear_errors AFTER DELETE ON particles
-                FOR EACH ROW
-                BEGIN
-                    -- remove all errors for that particle
-                    DELETE FROM errors WHERE particle_id = OLD.particle_id;
-                    -- decrement number of particles
-                    UPDATE config_table SET value = value - 1 WHERE parameter = 'count_particles';
-                END;
-
-            -- when a particle is inserted, increment the counter
-            CREATE TRIGGER IF NOT EXISTS particles_count_insert_trigger AFTER INSERT ON particles
-                FOR EACH ROW
-                BEGIN
-                  UPDATE config_table SET value = value + 1 WHERE parameter = 'count_particles';
-                END;
-
-            -- when a particle error is inserted, store particle id if it wasn't there yet
-            CREATE TRIGGER IF NOT EXISTS store_particle_id AFTER INSERT ON errors
-                FOR EACH ROW
-                BEGIN
-                    INSERT OR IGNORE INTO particles (particle_id, timestamp) VALUES (NEW.particle_id, NEW.timestamp);
-                END;
-
-            CREATE TABLE IF NOT EXISTS logs (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                timestamp INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
-                log TEXT
-            );
-
-            CREATE TRIGGER IF NOT EXISTS logs_insert_and_limit_trigger AFTER INSERT ON logs
-                FOR EACH ROW
-                BEGIN
-                    -- when a log is inserted, increment the counter
-                    UPDATE config_table SET value = value + 1 WHERE parameter = 'count_logs';
-
-                    -- if there are more than `max_logs` logs, delete the oldest ones
-                    DELETE FROM logs
-                    WHERE (SELECT value FROM config_table WHERE parameter = 'count_logs')
-                        > (SELECT value FROM config_table WHERE parameter = 'max_logs')
-                    AND id = (SELECT id FROM logs ORDER BY timestamp ASC, id ASC LIMIT 1);
-
-                    -- decrement number of logs
-                    UPDATE config_table SET value = value - 1 WHERE parameter = 'count_logs'
-                    AND (SELECT value FROM config_table WHERE parameter = 'count_logs')
-                        > (SELECT value FROM config_table WHERE parameter = 'max_logs');
-                END;
-
-            CREATE TABLE IF NOT EXISTS mailbox (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                timestamp INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
-                init_peer_id TEXT,
-                message TEXT
-            );
-
-            CREATE TRIGGER IF NOT EXISTS mailbox_insert_and_limit_trigger AFTER INSERT ON mailbox
-                FOR EACH ROW
-                BEGIN
-                  -- when a mailbox message is inserted, increment the counter
-                  UPDATE config_table SET value = value + 1 WHERE parameter = 'count_mailbox';
-
-                  -- if there are more than `max_mailbox` messages, delete the oldest ones
-                  DELETE FROM mailbox
-                  WHERE (SELECT value FROM config_table WHERE parameter = 'count_mailbox')
-                        > (SELECT value FROM config_table WHERE parameter = 'max_mailbox')
-                  AND id = (SELECT id FROM mailbox ORDER BY timestamp ASC, id ASC LIMIT 1);
-
-                  -- decrement number of mailbox messages
-                  UPDATE config_table SET value = value - 1 WHERE parameter = 'count_mailbox'
-                  AND (SELECT value FROM config_table WHERE parameter = 'count_mailbox')
-                        > (SELECT value FROM config_table WHERE parameter = 'max_mailbox');
-                END;
-
-            "#),
-    )
-        .expect("init sqlite db");
+        r#"
+        CREATE TABLE IF NOT EXISTS trigger_config (
+            -- clock config
+            start_sec INTEGER, end_sec INTEGER, period_sec INTEGER,
+            -- connection pool config
+            connect INTEGER, disconnect INTEGER,
+            -- blockchain config
+            start_block INTEGER, end_block INTEGER
+        );
+
+        CREATE TABLE IF NOT EXISTS kv (
+            key TEXT NOT NULL,
+            string TEXT,
+            u32 INTEGER,
+            list_order INTEGER DEFAULT -1,
+
+            PRIMARY KEY(key, list_order)
+        );
+
+        -- particles stored in the database, LRU-like
+        CREATE TABLE IF NOT EXISTS particles (particle_id TEXT PRIMARY KEY, timestamp INTEGER);
+        -- errors happened in particles
+        CREATE TABLE IF NOT EXISTS errors (
+            particle_id TEXT,
+            timestamp INTEGER,
+            error_idx INTEGER,
+            error_code INTEGER,
+            instruction TEXT,
+            message TEXT,
+            peer_id TEXT
+        );
+        CREATE TABLE IF NOT EXISTS config_table (parameter TEXT PRIMARY KEY, value INTEGER NOT NULL);
+
+        -- if there are more than `max_particles` particles, delete the oldest one
+        CREATE TRIGGER IF NOT EXISTS errors_limit_trigger AFTER INSERT ON particles
+            FOR EACH ROW
+            -- if limit is reached
+            WHEN (SELECT value FROM config_table WHERE parameter = 'count_particles')
+                > (SELECT value FROM config_table WHERE parameter = 'max_particles')
+            BEGIN
+                -- delete all errors for the oldest particle
+                DELETE FROM particles
+                    -- take oldest by 'timestamp' column
+                    WHERE particle_id = (SELECT particle_id FROM particles ORDER BY timestamp LIMIT 1);
+            END;
+
+        -- when a particle is removed, remove its errors
+        CREATE TRIGGER IF NOT EXISTS clear_errors AFTER DELETE ON particles
+            FOR EACH ROW
+            BEGIN
+                -- remove all errors for that particle
+                DELETE FROM errors WHERE particle_id = OLD.particle_id;
+                -- decrement number of particles
+                UPDATE config_table SET value = value - 1 WHERE parameter = 'count_particles';
+            END;
+
+        -- when a particle is inserted, increment the counter
+        CREATE TRIGGER IF NOT EXISTS particles_count_insert_trigger AFTER INSERT ON particles
+            FOR EACH ROW
+            BEGIN
+              UPDATE config_table SET value = value + 1 WHERE parameter = 'count_particles';
+            END;
+
+        -- when a particle error is inserted, store particle id if it wasn't there yet
+        CREATE TRIGGER IF NOT EXISTS store_particle_id AFTER INSERT ON errors
+            FOR EACH ROW
+            BEGIN
+                INSERT OR IGNORE INTO particles (particle_id, timestamp) VALUES (NEW.particle_id, NEW.timestamp);
+            END;
+
+        CREATE TABLE IF NOT EXISTS logs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            timestamp INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            log TEXT
+        );
+
+        CREATE TRIGGER IF NOT EXISTS logs_insert_and_limit_trigger AFTER INSERT ON logs
+            FOR EACH ROW
+            BEGIN
+                -- when a log is inserted, increment the counter
+                UPDATE config_table SET value = value + 1 WHERE parameter = 'count_logs';
+
+                -- if there are more than `max_logs` logs, delete the oldest ones
+                DELETE FROM logs
+                WHERE (SELECT value FROM config_table WHERE parameter = 'count_logs')
+                    > (SELECT value FROM config_table WHERE parameter = 'max_logs')
+                AND id = (SELECT id FROM logs ORDER BY timestamp ASC, id ASC LIMIT 1);
+
+                -- decrement number of logs
+                UPDATE config_table SET value = value - 1 WHERE parameter = 'count_logs'
+                AND (SELECT value FROM config_table WHERE parameter = 'count_logs')
+                    > (SELECT value FROM config_table WHERE parameter = 'max_logs');
+            END;
+
+        CREATE TABLE IF NOT EXISTS mailbox (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            timestamp INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            init_peer_id TEXT,
+            message TEXT
+        );
+
+        CREATE TRIGGER IF NOT EXISTS mailbox_insert_and_limit_trigger AFTER INSERT ON mailbox
+            FOR EACH ROW
+            BEGIN
+              -- when a mailbox message is inserted, increment the counter
+              UPDATE config_table SET value = value + 1 WHERE parameter = 'count_mailbox';
+
+              -- if there are more than `max_mailbox` messages, delete the oldest ones
+              DELETE FROM mailbox
+              WHERE (SELECT value FROM config_table WHERE parameter = 'count_mailbox')
+                    > (SELECT value FROM config_table WHERE parameter = 'max_mailbox')
+              AND id = (SELECT id FROM mailbox ORDER BY timestamp ASC, id ASC LIMIT 1);
+
+              -- decrement number of mailbox messages
+              UPDATE config_table SET value = value - 1 WHERE parameter = 'count_mailbox'
+              AND (SELECT value FROM config_table WHERE parameter = 'count_mailbox')
+                    > (SELECT value FROM config_table WHERE parameter = 'max_mailbox');
+            END;
+        "#,
+    )?;
+
+    Ok(())
+}
+
+fn add_typed_kv_columns(conn: &Connection) -> eyre::Result<()> {
+    add_column(conn, "kv", "i64", "INTEGER")?;
+    add_column(conn, "kv", "f64", "REAL")?;
+    add_column(conn, "kv", "bool", "INTEGER")?;
+    add_column(conn, "kv", "bytes", "BLOB")?;
+
+    Ok(())
+}
+
+fn add_kv_expiry(conn: &Connection) -> eyre::Result<()> {
+    // unix timestamp in seconds after which the value is considered absent, NULL for no expiry
+    add_column(conn, "kv", "expires_at", "INTEGER")?;
+    conn.execute(
+        r#"
+        CREATE INDEX IF NOT EXISTS kv_expires_at ON kv (expires_at);
+
+        -- purge expired keys whenever something is written to the kv
+        CREATE TRIGGER IF NOT EXISTS kv_expire_trigger AFTER INSERT ON kv
+            FOR EACH ROW
+            BEGIN
+                DELETE FROM kv WHERE expires_at <= strftime('%s', 'now');
+            END;
+        "#,
+    )?;
+
+    Ok(())
+}
+
+fn create_kv_map(conn: &Connection) -> eyre::Result<()> {
+    conn.execute(
+        r#"
+        -- fields of map values, the permissions are derived from the `key`
+        CREATE TABLE IF NOT EXISTS kv_map (
+            key TEXT NOT NULL,
+            field TEXT NOT NULL,
+            value TEXT,
+
+            PRIMARY KEY(key, field)
+        );
+        "#,
+    )?;
+
+    Ok(())
+}
+
+/// Adds `value_type` naming the column that holds the value: `string`, `u32`, `i64`, `f64`, `bool` or `bytes`.
+/// Before that empty strings were stored as NULL, so untyped values are strings and NULL becomes ''.
+fn add_kv_value_types(conn: &Connection) -> eyre::Result<()> {
+    add_column(conn, "kv", "value_type", "TEXT")?;
+    conn.execute(
+        r#"
+        UPDATE kv
+           SET value_type = CASE
+                   WHEN u32 IS NOT NULL THEN 'u32'
+                   WHEN i64 IS NOT NULL THEN 'i64'
+                   WHEN f64 IS NOT NULL THEN 'f64'
+                   WHEN bool IS NOT NULL THEN 'bool'
+                   WHEN bytes IS NOT NULL THEN 'bytes'
+                   ELSE 'string'
+               END
+         WHERE value_type IS NULL;
+
+        UPDATE kv SET string = '' WHERE value_type = 'string' AND string IS NULL;
+        "#,
+    )?;
+
+    Ok(())
+}
+
+/// Adds the level and the tags of logs. Tags are stored as `,tag1,tag2,` to be matched with `instr`.
+fn add_log_levels(conn: &Connection) -> eyre::Result<()> {
+    add_column(conn, "logs", "level", "TEXT NOT NULL DEFAULT 'info'")?;
+    add_column(conn, "logs", "tags", "TEXT")?;
+
+    Ok(())
+}
+
+/// Marks logs stored by `store_structured_log`, their text is a JSON object.
+fn add_structured_logs(conn: &Connection) -> eyre::Result<()> {
+    add_column(conn, "logs", "structured", "INTEGER NOT NULL DEFAULT 0")
+}
+
+/// Links logs to the particles that stored them, and records when errors were stored
+/// to order them along with the logs of the particle.
+fn add_particle_tracing(conn: &Connection) -> eyre::Result<()> {
+    add_column(conn, "logs", "particle_id", "TEXT")?;
+    add_column(conn, "logs", "particle_timestamp", "INTEGER")?;
+    add_column(conn, "errors", "stored_at", "INTEGER")?;
+    conn.execute("CREATE INDEX IF NOT EXISTS logs_particle_id ON logs (particle_id)")?;
+
+    Ok(())
+}
+
+/// Runs of the spell script recorded by `record_run`, one per particle.
+fn create_runs(conn: &Connection) -> eyre::Result<()> {
+    conn.execute(
+        r#"
+        CREATE TABLE IF NOT EXISTS runs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            particle_id TEXT NOT NULL UNIQUE,
+            counter INTEGER NOT NULL,
+            particle_timestamp INTEGER NOT NULL,
+            timestamp INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+        );
+
+        CREATE TRIGGER IF NOT EXISTS runs_insert_and_limit_trigger AFTER INSERT ON runs
+            FOR EACH ROW
+            BEGIN
+                -- when a run is inserted, increment the counters
+                UPDATE config_table SET value = value + 1 WHERE parameter IN ('count_runs', 'total_runs');
+
+                -- if there are more than `max_runs` runs, delete the oldest ones
+                DELETE FROM runs
+                WHERE (SELECT value FROM config_table WHERE parameter = 'count_runs')
+                    > (SELECT value FROM config_table WHERE parameter = 'max_runs')
+                AND id = (SELECT id FROM runs ORDER BY id ASC LIMIT 1);
+
+                -- decrement number of runs
+                UPDATE config_table SET value = value - 1 WHERE parameter = 'count_runs'
+                AND (SELECT value FROM config_table WHERE parameter = 'count_runs')
+                    > (SELECT value FROM config_table WHERE parameter = 'max_runs');
+            END;
+        "#,
+    )?;
+
+    Ok(())
+}
+
+/// Adds the time an error was last seen and the number of its occurrences, see `set_error_deduplication`.
+fn add_error_occurrences(conn: &Connection) -> eyre::Result<()> {
+    add_column(conn, "errors", "last_seen", "INTEGER")?;
+    add_column(conn, "errors", "occurrences", "INTEGER NOT NULL DEFAULT 1")?;
+    conn.execute(
+        "UPDATE errors SET last_seen = COALESCE(stored_at, timestamp / 1000) WHERE last_seen IS NULL",
+    )?;
+
+    Ok(())
+}
+
+/// Adds the time until which a message received with `mailbox_receive` is hidden, NULL for visible messages.
+fn add_mailbox_visibility(conn: &Connection) -> eyre::Result<()> {
+    add_column(conn, "mailbox", "visible_at", "INTEGER")
+}
+
+/// Adds the topic of mailbox messages, `''` is the default topic.
+/// Every topic keeps `max_mailbox` latest messages unless `max_mailbox:<topic>` is set in `config_table`.
+fn add_mailbox_topics(conn: &Connection) -> eyre::Result<()> {
+    add_column(conn, "mailbox", "topic", "TEXT NOT NULL DEFAULT ''")?;
+    conn.execute(
+        r#"
+        CREATE INDEX IF NOT EXISTS mailbox_topic ON mailbox (topic, id);
+
+        DROP TRIGGER IF EXISTS mailbox_insert_and_limit_trigger;
+        CREATE TRIGGER mailbox_insert_and_limit_trigger AFTER INSERT ON mailbox
+            FOR EACH ROW
+            BEGIN
+              -- if there are more messages in the topic than its limit, delete the oldest one
+              DELETE FROM mailbox
+              WHERE (SELECT COUNT(*) FROM mailbox WHERE topic = NEW.topic)
+                    > COALESCE(
+                        (SELECT value FROM config_table WHERE parameter = 'max_mailbox:' || NEW.topic),
+                        (SELECT value FROM config_table WHERE parameter = 'max_mailbox')
+                    )
+              AND id = (SELECT id FROM mailbox WHERE topic = NEW.topic ORDER BY timestamp ASC, id ASC LIMIT 1);
+
+              -- update the number of mailbox messages
+              UPDATE config_table SET value = (SELECT COUNT(*) FROM mailbox) WHERE parameter = 'count_mailbox';
+            END;
+        "#,
+    )?;
+
+    Ok(())
+}
+
+/// Peers allowed to push to the mailbox and the rate limit windows of the senders, see `set_mailbox_policy`.
+fn create_mailbox_senders(conn: &Connection) -> eyre::Result<()> {
+    conn.execute(
+        r#"
+        CREATE TABLE IF NOT EXISTS mailbox_allowlist (peer_id TEXT PRIMARY KEY);
+
+        -- number of messages pushed by the peer since `window_start`, unix time in seconds
+        CREATE TABLE IF NOT EXISTS mailbox_senders (
+            peer_id TEXT PRIMARY KEY,
+            window_start INTEGER NOT NULL,
+            count INTEGER NOT NULL
+        );
+        "#,
+    )?;
+
+    Ok(())
+}
+
+/// Adds triggers keeping the number of keys and bytes in `kv` and `kv_map` in `config_table`,
+/// so the KV quotas are checked without scanning the tables.
+/// `INSERT OR REPLACE` doesn't fire the delete triggers, so the replaced row is subtracted before the insert.
+fn add_kv_usage_counters(conn: &Connection) -> eyre::Result<()> {
+    let kv_size = size_of(KV_COLUMNS);
+    let new_kv_size = size_of_row("NEW", KV_COLUMNS);
+    let old_kv_size = size_of_row("OLD", KV_COLUMNS);
+    let map_size = size_of(KV_MAP_COLUMNS);
+    let new_map_size = size_of_row("NEW", KV_MAP_COLUMNS);
+    let old_map_size = size_of_row("OLD", KV_MAP_COLUMNS);
+    conn.execute(f!(r#"
+        CREATE TRIGGER IF NOT EXISTS kv_usage_insert_trigger BEFORE INSERT ON kv
+            FOR EACH ROW
+            BEGIN
+                UPDATE config_table SET value = value + 1 WHERE parameter = 'kv_keys'
+                    AND NOT EXISTS (SELECT 1 FROM kv WHERE key = NEW.key)
+                    AND NOT EXISTS (SELECT 1 FROM kv_map WHERE key = NEW.key);
+                UPDATE config_table SET value = value + {new_kv_size} - COALESCE(
+                    (SELECT {kv_size} FROM kv WHERE key = NEW.key AND list_order = NEW.list_order), 0
+                ) WHERE parameter = 'kv_bytes';
+            END;
+
+        CREATE TRIGGER IF NOT EXISTS kv_usage_update_trigger AFTER UPDATE ON kv
+            FOR EACH ROW
+            BEGIN
+                UPDATE config_table SET value = value + {new_kv_size} - {old_kv_size} WHERE parameter = 'kv_bytes';
+            END;
+
+        CREATE TRIGGER IF NOT EXISTS kv_usage_delete_trigger AFTER DELETE ON kv
+            FOR EACH ROW
+            BEGIN
+                UPDATE config_table SET value = value - 1 WHERE parameter = 'kv_keys'
+                    AND NOT EXISTS (SELECT 1 FROM kv WHERE key = OLD.key)
+                    AND NOT EXISTS (SELECT 1 FROM kv_map WHERE key = OLD.key);
+                UPDATE config_table SET value = value - {old_kv_size} WHERE parameter = 'kv_bytes';
+            END;
+
+        CREATE TRIGGER IF NOT EXISTS kv_map_usage_insert_trigger BEFORE INSERT ON kv_map
+            FOR EACH ROW
+            BEGIN
+                UPDATE config_table SET value = value + 1 WHERE parameter = 'kv_keys'
+                    AND NOT EXISTS (SELECT 1 FROM kv WHERE key = NEW.key)
+                    AND NOT EXISTS (SELECT 1 FROM kv_map WHERE key = NEW.key);
+                UPDATE config_table SET value = value + {new_map_size} - COALESCE(
+                    (SELECT {map_size} FROM kv_map WHERE key = NEW.key AND field = NEW.field), 0
+                ) WHERE parameter = 'kv_bytes';
+            END;
+
+        CREATE TRIGGER IF NOT EXISTS kv_map_usage_update_trigger AFTER UPDATE ON kv_map
+            FOR EACH ROW
+            BEGIN
+                UPDATE config_table SET value = value + {new_map_size} - {old_map_size} WHERE parameter = 'kv_bytes';
+            END;
+
+        CREATE TRIGGER IF NOT EXISTS kv_map_usage_delete_trigger AFTER DELETE ON kv_map
+            FOR EACH ROW
+            BEGIN
+                UPDATE config_table SET value = value - 1 WHERE parameter = 'kv_keys'
+                    AND NOT EXISTS (SELECT 1 FROM kv WHERE key = OLD.key)
+                    AND NOT EXISTS (SELECT 1 FROM kv_map WHERE key = OLD.key);
+                UPDATE config_table SET value = value - {old_map_size} WHERE parameter = 'kv_bytes';
+            END;
+        "#))?;
+
+    Ok(())
+}
+
+/// Makes `max_mailbox` cap the whole mailbox again instead of every topic,
+/// so pushing to new topics can't grow the mailbox without a limit.
+/// Topics with their own limit, see `set_mailbox_topic_limit`, are capped by it as well.
+fn cap_mailbox_total(conn: &Connection) -> eyre::Result<()> {
+    conn.execute(
+        r#"
+        DROP TRIGGER IF EXISTS mailbox_insert_and_limit_trigger;
+        CREATE TRIGGER mailbox_insert_and_limit_trigger AFTER INSERT ON mailbox
+            FOR EACH ROW
+            BEGIN
+              -- if there are more messages in the topic than its own limit, delete the oldest one
+              DELETE FROM mailbox
+              WHERE (SELECT COUNT(*) FROM mailbox WHERE topic = NEW.topic)
+                    > (SELECT value FROM config_table WHERE parameter = 'max_mailbox:' || NEW.topic)
+              AND id = (SELECT id FROM mailbox WHERE topic = NEW.topic ORDER BY timestamp ASC, id ASC LIMIT 1);
+
+              -- if there are more than `max_mailbox` messages in all topics, delete the oldest one
+              DELETE FROM mailbox
+              WHERE (SELECT COUNT(*) FROM mailbox)
+                    > (SELECT value FROM config_table WHERE parameter = 'max_mailbox')
+              AND id = (SELECT id FROM mailbox ORDER BY timestamp ASC, id ASC LIMIT 1);
+
+              -- update the number of mailbox messages
+              UPDATE config_table SET value = (SELECT COUNT(*) FROM mailbox) WHERE parameter = 'count_mailbox';
+            END;
+        "#,
+    )?;
+
+    Ok(())
+}
+
+/// Makes `max_mailbox` the limit of every topic again, so a flood on one topic can't evict
+/// the messages of other topics. The whole mailbox is capped by the separate `max_mailbox_total`.
+fn limit_mailbox_topics(conn: &Connection) -> eyre::Result<()> {
+    conn.execute(
+        r#"
+        DROP TRIGGER IF EXISTS mailbox_insert_and_limit_trigger;
+        CREATE TRIGGER mailbox_insert_and_limit_trigger AFTER INSERT ON mailbox
+            FOR EACH ROW
+            BEGIN
+              -- if there are more messages in the topic than its limit, delete the oldest one
+              DELETE FROM mailbox
+              WHERE (SELECT COUNT(*) FROM mailbox WHERE topic = NEW.topic)
+                    > COALESCE(
+                        (SELECT value FROM config_table WHERE parameter = 'max_mailbox:' || NEW.topic),
+                        (SELECT value FROM config_table WHERE parameter = 'max_mailbox')
+                    )
+              AND id = (SELECT id FROM mailbox WHERE topic = NEW.topic ORDER BY timestamp ASC, id ASC LIMIT 1);
+
+              -- if there are more than `max_mailbox_total` messages in all topics, delete the oldest one
+              DELETE FROM mailbox
+              WHERE (SELECT COUNT(*) FROM mailbox)
+                    > (SELECT value FROM config_table WHERE parameter = 'max_mailbox_total')
+              AND id = (SELECT id FROM mailbox ORDER BY timestamp ASC, id ASC LIMIT 1);
+
+              -- update the number of mailbox messages
+              UPDATE config_table SET value = (SELECT COUNT(*) FROM mailbox) WHERE parameter = 'count_mailbox';
+            END;
+        "#,
+    )?;
+
+    Ok(())
+}
+
+#[test_env_helpers::after_each]
+#[cfg(test)]
+mod tests {
+    use marine_rs_sdk::CallParameters;
+    use marine_rs_sdk::ParticleParameters;
+    use marine_rs_sdk_test::marine_test;
+
+    use super::MIGRATIONS;
+
+    const DB_FILE: &str = "./tests_artifacts/spell.sqlite";
+    /// Database created by the spell before the schema versioning, see `create_tables`
+    const DB_FILE_V0: &str = "./tests_artifacts/spell_v0.sqlite";
+
+    #[ctor::ctor]
+    /// usage of 'ctor' makes this function run only once
+    fn before_all_tests() {
+        std::fs::remove_file(DB_FILE).ok();
+    }
+
+    /// after_each macro copy-pastes this function into every test
+    fn after_each() {
+        std::fs::remove_file(DB_FILE).ok();
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_schema_version(spell: marine_test_env::spell::ModuleInterface) {
+        let version = spell.get_schema_version();
+        assert!(
+            version.success,
+            "get_schema_version failed: {}",
+            version.error
+        );
+        assert_eq!(version.value as usize, MIGRATIONS.len());
+    }
+
+    #[marine_test(spell(config_path = "../tests_artifacts/Config.toml"))]
+    fn test_upgrade_from_v0() {
+        std::fs::copy(DB_FILE_V0, DB_FILE).expect("copy the old database");
+        // migrations are applied on the service start
+        let mut spell = marine_test_env::spell::ServiceInterface::new();
+
+        let version = spell.get_schema_version();
+        assert!(
+            version.success,
+            "get_schema_version failed: {}",
+            version.error
+        );
+        assert_eq!(version.value as usize, MIGRATIONS.len());
+
+        let get = spell.get_string("str".into());
+        assert_eq!(get.value, "a", "get_string failed: {}", get.error);
+        let get = spell.get_string("empty".into());
+        assert!(!get.absent, "empty string must survive the upgrade");
+        assert_eq!(get.value, "", "get_string failed: {}", get.error);
+        let get = spell.get_u32("num".into());
+        assert_eq!(get.value, 5, "get_u32 failed: {}", get.error);
+        let list = spell.list_get_strings("list".into());
+        assert_eq!(
+            list.value,
+            vec!["a", "", "b"],
+            "list_get_strings failed: {}",
+            list.error
+        );
+        let removed = spell.list_remove_string_cp("list".into(), "".into(), spell_call_params());
+        assert!(
+            removed.success,
+            "list_remove_string failed: {}",
+            removed.error
+        );
+        assert_eq!(spell.list_get_strings("list".into()).value, vec!["a", "b"]);
+
+        let logs = spell.get_logs();
+        assert!(logs.success, "get_logs failed: {}", logs.error);
+        assert_eq!(logs.logs.len(), 1, "logs must survive the upgrade");
+
+        let set = spell.set_i64_cp("i64".into(), -1, spell_call_params());
+        assert!(set.success, "set_i64 failed: {}", set.error);
+        let set = spell.map_set_cp("map".into(), "f".into(), "v".into(), spell_call_params());
+        assert!(set.success, "map_set failed: {}", set.error);
+    }
+
+    #[marine_test(spell(config_path = "../tests_artifacts/Config.toml"))]
+    fn test_migrations_applied_once() {
+        let mut spell = marine_test_env::spell::ServiceInterface::new();
+        let set = spell.set_string_cp("str".into(), "".into(), spell_call_params());
+        assert!(set.success, "set_string failed: {}", set.error);
+
+        // restart the service on the same database
+        let mut spell = marine_test_env::spell::ServiceInterface::new();
+        let version = spell.get_schema_version();
+        assert_eq!(version.value as usize, MIGRATIONS.len());
+        let get = spell.get_string("str".into());
+        assert!(!get.absent, "value must survive the restart");
+        assert_eq!(get.value, "", "get_string failed: {}", get.error);
+    }
+
+    fn spell_call_params() -> CallParameters {
+        CallParameters {
+            particle: ParticleParameters {
+                init_peer_id: "worker-id".to_string(),
+                id: "spell_spell-id_0".to_string(),
+                ..<_>::default()
+            },
+            service_creator_peer_id: "worker-id".to_string(),
+            service_id: "spell-id".to_string(),
+            worker_id: "worker-id".to_string(),
+            host_id: "host-id".to_string(),
+            tetraplets: vec![],
+        }
+    }
 }
@@ -0,0 +1,249 @@
+/*
+ * Aqua Spell Service
+ *
+ * Copyright (C) 2024 Fluence DAO
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation version 3 of the
+ * License.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use marine_rs_sdk::marine;
+use marine_sqlite_connector::Connection;
+
+use fluence_spell_dtos::stats::{KeySize, StorageStats, StorageStatsValue, TableStats};
+
+use crate::misc::{fetch_rows, read_config, read_u64};
+use crate::schema::{db, DB_FILE};
+
+/// Columns of `kv` and `kv_map` holding the stored data
+pub const KV_COLUMNS: &[&str] = &["key", "string", "u32", "i64", "f64", "bool", "bytes"];
+pub const KV_MAP_COLUMNS: &[&str] = &["key", "field", "value"];
+
+/// Tables to report: name, columns holding the stored data and the row counter in `config_table`
+const TABLES: [(&str, &[&str], Option<&str>); 7] = [
+    ("kv", KV_COLUMNS, None),
+    ("kv_map", KV_MAP_COLUMNS, None),
+    ("logs", &["log", "level", "tags"], Some("count_logs")),
+    (
+        "mailbox",
+        &["init_peer_id", "message", "topic"],
+        Some("count_mailbox"),
+    ),
+    (
+        "errors",
+        &["particle_id", "instruction", "message", "peer_id"],
+        None,
+    ),
+    ("particles", &["particle_id"], Some("count_particles")),
+    ("runs", &["particle_id"], Some("count_runs")),
+];
+
+/// SQL expression summing up the sizes of the `columns` of a row
+pub fn size_of(columns: &[&str]) -> String {
+    columns
+        .iter()
+        .map(|column| f!("COALESCE(length(CAST({column} AS BLOB)), 0)"))
+        .collect::<Vec<_>>()
+        .join(" + ")
+}
+
+/// Same as `size_of` for the `row` of a trigger, `NEW` or `OLD`
+pub fn size_of_row(row: &str, columns: &[&str]) -> String {
+    let columns: Vec<String> = columns.iter().map(|column| f!("{row}.{column}")).collect();
+    size_of(&columns.iter().map(String::as_str).collect::<Vec<_>>())
+}
+
+fn table_stats(
+    conn: &Connection,
+    table: &str,
+    columns: &[&str],
+    counter: Option<&str>,
+) -> eyre::Result<TableStats> {
+    let size = size_of(columns);
+    let rows = read_u64(conn, &f!("SELECT COUNT(*) FROM {table}"))?;
+    let bytes = read_u64(conn, &f!("SELECT SUM({size}) FROM {table}"))?;
+    let counter = match counter {
+        Some(counter) => Some(read_config(conn, counter)?),
+        None => None,
+    };
+
+    Ok(TableStats {
+        table: table.to_string(),
+        rows,
+        bytes,
+        counter: counter.unwrap_or_default(),
+        counter_drift: counter.map_or(false, |counter| counter != rows),
+    })
+}
+
+/// Stats of `kv` and `kv_map` together, checked against the `kv_keys` and `kv_bytes` counters
+/// the quotas are enforced with. The rows are the distinct keys, the counter is `kv_keys`.
+fn kv_usage_stats(conn: &Connection) -> eyre::Result<TableStats> {
+    let kv_size = size_of(KV_COLUMNS);
+    let map_size = size_of(KV_MAP_COLUMNS);
+    let keys = read_u64(
+        conn,
+        "SELECT COUNT(*) FROM (SELECT key FROM kv UNION SELECT key FROM kv_map)",
+    )?;
+    let bytes = read_u64(conn, &f!("SELECT SUM({kv_size}) FROM kv"))?
+        + read_u64(conn, &f!("SELECT SUM({map_size}) FROM kv_map"))?;
+    let counter = read_config(conn, "kv_keys")?;
+
+    Ok(TableStats {
+        table: "kv_usage".to_string(),
+        rows: keys,
+        bytes,
+        counter,
+        counter_drift: counter != keys || read_config(conn, "kv_bytes")? != bytes,
+    })
+}
+
+#[marine]
+/// Get the number of rows and the size of the data stored in every table,
+/// `top_keys` largest KV keys and the size of the database.
+/// Counters that went out of sync with the tables are flagged with `counter_drift`,
+/// the KV usage counters are reported as the `kv_usage` table.
+pub fn get_storage_stats(top_keys: u32) -> StorageStatsValue {
+    let result: eyre::Result<StorageStats> = try {
+        let conn = db();
+        let mut tables = TABLES
+            .iter()
+            .map(|(table, columns, counter)| table_stats(&conn, table, columns, *counter))
+            .collect::<eyre::Result<Vec<_>>>()?;
+        tables.push(kv_usage_stats(&conn)?);
+
+        let kv_size = size_of(KV_COLUMNS);
+        let map_size = size_of(KV_MAP_COLUMNS);
+        let mut statement = conn.prepare(f!(r#"
+            SELECT key, SUM(size) AS size
+              FROM (SELECT key, {kv_size} AS size FROM kv
+                    UNION ALL
+                    SELECT key, {map_size} AS size FROM kv_map)
+          GROUP BY key
+          ORDER BY size DESC, key
+             LIMIT ?
+            "#))?;
+        statement.bind(1, top_keys as i64)?;
+        let largest_keys = fetch_rows(statement, |statement| {
+            Ok(Some(KeySize {
+                key: statement.read::<String>(0)?,
+                bytes: statement.read::<i64>(1)? as u64,
+            }))
+        });
+
+        StorageStats {
+            tables,
+            largest_keys,
+            page_count: read_u64(&conn, "SELECT * FROM pragma_page_count()")?,
+            page_size: read_u64(&conn, "SELECT * FROM pragma_page_size()")?,
+            file_size: std::fs::metadata(DB_FILE)?.len(),
+        }
+    };
+
+    result.into()
+}
+
+#[test_env_helpers::after_each]
+#[cfg(test)]
+mod tests {
+    use marine_rs_sdk::CallParameters;
+    use marine_rs_sdk::ParticleParameters;
+    use marine_rs_sdk_test::marine_test;
+
+    const DB_FILE: &str = "./tests_artifacts/spell.sqlite";
+
+    #[ctor::ctor]
+    /// usage of 'ctor' makes this function run only once
+    fn before_all_tests() {
+        std::fs::remove_file(DB_FILE).ok();
+    }
+
+    /// after_each macro copy-pastes this function into every test
+    fn after_each() {
+        std::fs::remove_file(DB_FILE).ok();
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_storage_stats(spell: marine_test_env::spell::ModuleInterface) {
+        let cp = || spell_call_params();
+        let _ = spell.set_string_cp("small".into(), "a".into(), cp());
+        let _ = spell.set_string_cp("large".into(), "a".repeat(100), cp());
+        let _ = spell.list_push_string_cp("list".into(), "abc".into(), cp());
+        let _ = spell.list_push_string_cp("list".into(), "abc".into(), cp());
+        let _ = spell.map_set_cp("map".into(), "f".into(), "a".repeat(50), cp());
+        let _ = spell.store_log_cp("log".into(), cp());
+        let _ = spell.store_log_cp("log".into(), cp());
+        let _ = spell.push_mailbox_cp("message".into(), cp());
+
+        let stats = spell.get_storage_stats(2);
+        assert!(stats.success, "get_storage_stats failed: {}", stats.error);
+        let stats = stats.stats;
+
+        let table = |name: &str| {
+            stats
+                .tables
+                .iter()
+                .find(|table| table.table == name)
+                .unwrap_or_else(|| panic!("no stats for {}", name))
+                .clone()
+        };
+        let kv = table("kv");
+        assert_eq!(kv.rows, 4);
+        assert_eq!(
+            kv.bytes,
+            ("small".len() + 1 + "large".len() + 100 + 2 * ("list".len() + 3)) as u64
+        );
+        let kv_usage = table("kv_usage");
+        assert_eq!(kv_usage.rows, 4);
+        assert_eq!(kv_usage.counter, 4);
+        assert_eq!(kv_usage.bytes, kv.bytes + table("kv_map").bytes);
+        assert!(!kv_usage.counter_drift, "KV usage counters must be in sync");
+        let logs = table("logs");
+        assert_eq!(logs.rows, 2);
+        assert_eq!(logs.counter, 2);
+        assert!(!logs.counter_drift, "logs counter must be in sync");
+        let mailbox = table("mailbox");
+        assert_eq!(mailbox.rows, 1);
+        assert!(!mailbox.counter_drift, "mailbox counter must be in sync");
+        assert_eq!(table("errors").rows, 0);
+        assert!(!table("particles").counter_drift);
+
+        let keys: Vec<_> = stats
+            .largest_keys
+            .iter()
+            .map(|key| key.key.as_str())
+            .collect();
+        assert_eq!(keys, vec!["large", "map"]);
+        assert_eq!(stats.largest_keys[1].bytes, ("map".len() + 1 + 50) as u64);
+        assert_eq!(stats.largest_keys[0].bytes, ("large".len() + 100) as u64);
+
+        assert!(stats.page_count > 0);
+        assert!(stats.page_size > 0);
+        assert!(stats.file_size > 0);
+    }
+
+    fn spell_call_params() -> CallParameters {
+        CallParameters {
+            particle: ParticleParameters {
+                init_peer_id: "worker-id".to_string(),
+                id: "spell_spell-id_0".to_string(),
+                ..<_>::default()
+            },
+            service_creator_peer_id: "worker-id".to_string(),
+            service_id: "spell-id".to_string(),
+            worker_id: "worker-id".to_string(),
+            host_id: "host-id".to_string(),
+            tetraplets: vec![],
+        }
+    }
+}
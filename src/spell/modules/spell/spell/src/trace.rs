@@ -0,0 +1,180 @@
+/*
+ * Aqua Spell Service
+ *
+ * Copyright (C) 2024 Fluence DAO
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation version 3 of the
+ * License.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::convert::TryFrom;
+
+use marine_rs_sdk::marine;
+
+use fluence_spell_dtos::value::Log;
+
+use crate::error_handling::{not_expired, LastErrorEntry, ERROR_COLUMNS, ERROR_STORED_AT};
+use crate::log::{read_log, LOG_COLUMNS};
+use crate::misc::fetch_rows;
+use crate::schema::db;
+
+#[marine]
+#[derive(Default, Debug)]
+/// A log or an error of a particle. `kind` is `log` or `error` and tells which of the fields is set.
+/// `timestamp` is the unix time in seconds when the entry was stored.
+pub struct TraceEntry {
+    pub timestamp: u64,
+    pub kind: String,
+    pub log: Log,
+    pub error: LastErrorEntry,
+}
+
+#[marine]
+pub struct ParticleTrace {
+    pub entries: Vec<TraceEntry>,
+    pub success: bool,
+    pub error: String,
+}
+
+#[marine]
+/// Get the logs and the errors stored by the particle ordered by the time they were stored.
+/// The time is in seconds, so logs go before errors stored in the same second.
+pub fn get_particle_trace(particle_id: String) -> ParticleTrace {
+    let result: eyre::Result<Vec<TraceEntry>> = try {
+        let conn = db();
+        let not_expired = not_expired(&conn)?;
+        let mut statement = conn.prepare(f!(
+            "SELECT {LOG_COLUMNS} FROM logs WHERE particle_id = ? ORDER BY id ASC"
+        ))?;
+        statement.bind(1, particle_id.as_str())?;
+        let logs = fetch_rows(statement, |statement| {
+            Ok(read_log(statement)?.map(|log| TraceEntry {
+                timestamp: log.timestamp,
+                kind: "log".to_string(),
+                log,
+                ..<_>::default()
+            }))
+        });
+
+        let mut statement = conn.prepare(f!(r#"
+            SELECT {ERROR_COLUMNS}, {ERROR_STORED_AT}
+              FROM errors
+             WHERE particle_id = ? AND {not_expired}
+          ORDER BY rowid ASC
+            "#))?;
+        statement.bind(1, particle_id.as_str())?;
+        let errors = fetch_rows(statement, |statement| {
+            let timestamp = statement.read::<i64>(7)? as u64;
+            Ok(Some(TraceEntry {
+                timestamp,
+                kind: "error".to_string(),
+                error: LastErrorEntry::try_from(statement)?,
+                ..<_>::default()
+            }))
+        });
+
+        let mut entries: Vec<TraceEntry> = logs.into_iter().chain(errors).collect();
+        // the sort is stable, so the entries of the same kind keep the order they were stored in
+        entries.sort_by_key(|entry| entry.timestamp);
+        entries
+    };
+
+    match result {
+        Ok(entries) => ParticleTrace {
+            entries,
+            success: true,
+            error: String::new(),
+        },
+        Err(e) => ParticleTrace {
+            entries: vec![],
+            success: false,
+            error: format!("get_particle_trace error: {}", e),
+        },
+    }
+}
+
+#[test_env_helpers::after_each]
+#[cfg(test)]
+mod tests {
+    use marine_rs_sdk::ParticleParameters;
+    use marine_rs_sdk_test::marine_test;
+    use uuid::Uuid;
+
+    const DB_FILE: &str = "./tests_artifacts/spell.sqlite";
+
+    #[ctor::ctor]
+    /// usage of 'ctor' makes this function run only once
+    fn before_all_tests() {
+        std::fs::remove_file(DB_FILE).ok();
+    }
+
+    /// after_each macro copy-pastes this function into every test
+    fn after_each() {
+        std::fs::remove_file(DB_FILE).ok();
+    }
+
+    fn cp(service_id: String, particle_id: String) -> marine_rs_sdk_test::CallParameters {
+        marine_rs_sdk_test::CallParameters {
+            particle: ParticleParameters {
+                init_peer_id: "folex".to_string(),
+                id: particle_id,
+                timestamp: 1000,
+                ..<_>::default()
+            },
+            service_creator_peer_id: "folex".to_string(),
+            service_id,
+            host_id: "".to_string(),
+            worker_id: "".to_string(),
+            tetraplets: vec![],
+        }
+    }
+
+    #[marine_test(config_path = "../tests_artifacts/Config.toml")]
+    fn test_particle_trace(spell: marine_test_env::spell::ModuleInterface) {
+        use marine_test_env::spell::LastError;
+
+        let service_id = Uuid::new_v4();
+        let particle_id = format!("spell_{}_1", service_id);
+        let cp1 = cp(service_id.to_string(), particle_id.clone());
+        let cp2 = cp(service_id.to_string(), format!("spell_{}_2", service_id));
+
+        let store = spell.store_log_cp("first".into(), cp1.clone());
+        assert!(store.success, "{}", store.error);
+        let store = spell.store_log_cp("other particle".into(), cp2);
+        assert!(store.success, "{}", store.error);
+        let error = LastError {
+            error_code: 1,
+            instruction: "call".to_string(),
+            message: "failed".to_string(),
+            peer_id: "peer".to_string(),
+        };
+        let store = spell.store_error_cp(error, 0, 1000, cp1.clone());
+        assert!(store.success, "{}", store.error);
+
+        let logs = spell.get_logs().logs;
+        assert_eq!(logs[0].particle_id, particle_id);
+        assert_eq!(logs[0].particle_timestamp, 1000);
+
+        let trace = spell.get_particle_trace(particle_id);
+        assert!(trace.success, "get_particle_trace failed: {}", trace.error);
+        let kinds: Vec<_> = trace.entries.iter().map(|e| e.kind.as_str()).collect();
+        assert_eq!(kinds, vec!["log", "error"]);
+        assert_eq!(trace.entries[0].log.message, "first");
+        assert_eq!(trace.entries[1].error.last_error.message, "failed");
+        assert!(trace.entries[0].timestamp <= trace.entries[1].timestamp);
+
+        let trace = spell.get_particle_trace("unknown".into());
+        assert!(trace.success, "get_particle_trace failed: {}", trace.error);
+        assert!(trace.entries.is_empty());
+    }
+}